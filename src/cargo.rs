@@ -0,0 +1,189 @@
+// SPDX-License-Identifier: MIT
+
+// Support for "gen.cargo_metadata", which exposes the targets of a Cargo workspace as the
+// "cargo" global, so launch configurations can be generated for each binary, for example:
+//     { "gen.foreach": "@{cargo.bins}", "gen.as": "bin", "gen.template": { ... } }
+
+use std::path::Path;
+
+use serde_json::{Map, Value};
+
+/// The name of the global "gen.cargo_metadata" adds
+pub const GLOBAL_NAME: &str = "cargo";
+
+/// The lists of the "cargo" global, by target kind
+const TARGET_KINDS: &[(&str, &str)] = &[
+    ("bin", "bins"),
+    ("lib", "libs"),
+    ("example", "examples"),
+    ("test", "tests"),
+    ("bench", "benches"),
+];
+
+/// Returns the "cargo" global. source is the value of "gen.cargo_metadata": true to run
+/// `cargo metadata` in base_dir, or the filename of its saved output, relative to base_dir.
+/// Returns a message describing the problem on failure.
+pub fn global(source: &Value, base_dir: &Path) -> Result<Value, String> {
+    let metadata = match source {
+        Value::Bool(true) => run_cargo_metadata(base_dir)?,
+        Value::String(filename) => {
+            let path = base_dir.join(filename);
+            std::fs::read_to_string(&path)
+                .map_err(|e| format!("Can't read {}: {}", path.display(), e))?
+        }
+        _ => {
+            return Err(
+                "Expected true, to run cargo metadata, or the filename of its output".to_string(),
+            )
+        }
+    };
+
+    let metadata: Value = serde_json::from_str(&metadata)
+        .map_err(|e| format!("Can't parse the cargo metadata: {}", e))?;
+    global_from_metadata(&metadata)
+}
+
+fn run_cargo_metadata(dir: &Path) -> Result<String, String> {
+    // Cargo sets CARGO for the processes it runs
+    let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    let output = std::process::Command::new(cargo)
+        .args(["metadata", "--format-version", "1", "--no-deps"])
+        .current_dir(dir)
+        .output()
+        .map_err(|e| format!("Can't run cargo metadata: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "cargo metadata failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    String::from_utf8(output.stdout).map_err(|e| e.to_string())
+}
+
+/// Builds the "cargo" global from the output of `cargo metadata --format-version 1`.
+/// It has the workspace's packages, and lists of its targets by kind, like "bins" and "tests".
+/// Each target has its "name", "package" and "src_path".
+fn global_from_metadata(metadata: &Value) -> Result<Value, String> {
+    let Some(packages) = metadata.get("packages").and_then(Value::as_array) else {
+        return Err("The cargo metadata has no packages".to_string());
+    };
+
+    // Without --no-deps, dependencies are listed too
+    let members: Option<Vec<&Value>> = metadata
+        .get("workspace_members")
+        .and_then(Value::as_array)
+        .map(|members| members.iter().collect());
+    let is_member = |package: &Value| match (&members, package.get("id")) {
+        (Some(members), Some(id)) => members.contains(&id),
+        _ => true,
+    };
+
+    let field = |value: &Value, name: &str| value.get(name).cloned().unwrap_or(Value::Null);
+
+    let mut global = Map::new();
+    global.insert(
+        "workspace_root".to_string(),
+        field(metadata, "workspace_root"),
+    );
+    global.insert("packages".to_string(), Value::Array(Vec::new()));
+    for (_, list) in TARGET_KINDS {
+        global.insert(list.to_string(), Value::Array(Vec::new()));
+    }
+
+    let push = |global: &mut Map<String, Value>, list: &str, value: Value| {
+        if let Some(Value::Array(list)) = global.get_mut(list) {
+            list.push(value);
+        }
+    };
+
+    for package in packages.iter().filter(|package| is_member(package)) {
+        let mut package_entry = Map::new();
+        for name in ["name", "version", "manifest_path"] {
+            package_entry.insert(name.to_string(), field(package, name));
+        }
+        push(&mut global, "packages", Value::Object(package_entry));
+
+        let targets = package.get("targets").and_then(Value::as_array);
+        for target in targets.into_iter().flatten() {
+            let kinds = target.get("kind").and_then(Value::as_array);
+            let mut lists: Vec<&str> = kinds
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_str)
+                .filter_map(|kind| match TARGET_KINDS.iter().find(|(k, _)| *k == kind) {
+                    Some((_, list)) => Some(*list),
+                    // Libraries have kinds like "rlib" or "cdylib" too
+                    None => kind.ends_with("lib").then_some("libs"),
+                })
+                .collect();
+            lists.sort_unstable();
+            lists.dedup();
+
+            for list in lists {
+                let mut target_entry = Map::new();
+                target_entry.insert("name".to_string(), field(target, "name"));
+                target_entry.insert("package".to_string(), field(package, "name"));
+                target_entry.insert("src_path".to_string(), field(target, "src_path"));
+                push(&mut global, list, Value::Object(target_entry));
+            }
+        }
+    }
+
+    Ok(Value::Object(global))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_global_from_metadata() {
+        let metadata = serde_json::json!({
+            "packages": [
+                {
+                    "name": "app",
+                    "version": "1.0.0",
+                    "id": "path+file:///ws/app#1.0.0",
+                    "manifest_path": "/ws/app/Cargo.toml",
+                    "targets": [
+                        { "kind": ["bin"], "name": "app", "src_path": "/ws/app/src/main.rs" },
+                        { "kind": ["cdylib", "rlib"], "name": "core", "src_path": "/ws/app/src/lib.rs" },
+                        { "kind": ["rlib", "bench", "staticlib"], "name": "mixed", "src_path": "/ws/app/src/mixed.rs" },
+                        { "kind": ["custom-build"], "name": "build-script-build", "src_path": "/ws/app/build.rs" }
+                    ]
+                },
+                {
+                    "name": "serde",
+                    "version": "1.0.0",
+                    "id": "registry+https://github.com/rust-lang/crates.io-index#serde@1.0.0",
+                    "targets": [{ "kind": ["lib"], "name": "serde", "src_path": "/serde/lib.rs" }]
+                }
+            ],
+            "workspace_members": ["path+file:///ws/app#1.0.0"],
+            "workspace_root": "/ws"
+        });
+
+        let global = global_from_metadata(&metadata).unwrap();
+        assert_eq!(global["workspace_root"], "/ws");
+        assert_eq!(global["packages"].as_array().unwrap().len(), 1);
+        assert_eq!(
+            global["bins"],
+            serde_json::json!([{ "name": "app", "package": "app", "src_path": "/ws/app/src/main.rs" }])
+        );
+
+        // A library with several crate types is listed once, even if they aren't next to each other
+        let libs: Vec<&str> = global["libs"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|lib| lib["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(libs, vec!["core", "mixed"]);
+        assert_eq!(global["benches"].as_array().unwrap().len(), 1);
+        assert_eq!(global["tests"], serde_json::json!([]));
+
+        assert!(global_from_metadata(&serde_json::json!({})).is_err());
+    }
+}
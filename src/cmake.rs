@@ -0,0 +1,511 @@
+// SPDX-License-Identifier: MIT
+
+// Support for CMake projects: checks of the CMakePresets.json and CMakeUserPresets.json
+// generated from "gen.cmakepresets" and "gen.cmakeuserpresets", the VS Code tasks
+// "gen.cmake_tasks" derives from them, and "gen.cmake_build_dir",
+// which exposes the targets of a build directory as the "cmake" global, by reading the replies
+// of the CMake File API. CMake only writes them if a query was made before configuring.
+
+use std::path::Path;
+
+use serde_json::{Map, Value};
+
+/// The name of the global "gen.cmake_build_dir" adds
+pub const GLOBAL_NAME: &str = "cmake";
+
+/// Where the File API's queries and replies are, inside the build directory
+const FILE_API_DIR: &str = ".cmake/api/v1";
+
+/// The latest presets schema version we know about, the one of CMake 3.31
+const LATEST_PRESETS_VERSION: u64 = 10;
+
+/// Fields of the presets file, and the schema version which introduced them
+const VERSIONED_FIELDS: &[(&str, u64)] = &[
+    ("buildPresets", 2),
+    ("testPresets", 2),
+    ("include", 4),
+    ("packagePresets", 6),
+    ("workflowPresets", 6),
+    ("$schema", 8),
+];
+
+/// The lists of presets, which can refer to configure presets
+const PRESET_LISTS: &[&str] = &[
+    "configurePresets",
+    "buildPresets",
+    "testPresets",
+    "packagePresets",
+];
+
+/// Something wrong with presets. pointer is relative to the presets.
+/// Errors make CMake reject the file, while warnings might be fine.
+#[derive(Debug, PartialEq)]
+pub struct Problem {
+    pub message: String,
+    pub pointer: String,
+    pub is_error: bool,
+}
+
+impl Problem {
+    fn error(message: String, pointer: &str) -> Self {
+        Self {
+            message,
+            pointer: pointer.to_string(),
+            is_error: true,
+        }
+    }
+
+    fn warning(message: String, pointer: &str) -> Self {
+        Self {
+            message,
+            pointer: pointer.to_string(),
+            is_error: false,
+        }
+    }
+}
+
+/// Checks presets against their schema version: that the version is set, that the fields it
+/// uses exist in that version, and that preset names are unique and references to them valid
+/// included_configure_presets are the configure presets of implicitly included files, as
+/// CMakeUserPresets.json includes CMakePresets.json.
+pub fn check_presets(presets: &Value, included_configure_presets: &[&str]) -> Vec<Problem> {
+    let Some(obj) = presets.as_object() else {
+        return vec![Problem::error("Expected an object".to_string(), "")];
+    };
+
+    let Some(version) = obj.get("version") else {
+        return vec![Problem::error(
+            "Missing \"version\", the presets schema version".to_string(),
+            "",
+        )];
+    };
+    let Some(version) = version.as_u64().filter(|v| *v >= 1) else {
+        return vec![Problem::error(
+            format!("Invalid version {}, expected a number from 1", version),
+            "/version",
+        )];
+    };
+
+    let mut problems = Vec::new();
+    if version > LATEST_PRESETS_VERSION {
+        problems.push(Problem::warning(
+            format!(
+                "Version {} is newer than the latest known one, {}, so it isn't checked",
+                version, LATEST_PRESETS_VERSION
+            ),
+            "/version",
+        ));
+        return problems;
+    }
+
+    for (field, since) in VERSIONED_FIELDS {
+        if obj.contains_key(*field) && version < *since {
+            problems.push(Problem::error(
+                format!(
+                    "\"{}\" requires version {} or later, but version is {}",
+                    field, since, version
+                ),
+                &format!("/{}", field),
+            ));
+        }
+    }
+
+    let mut configure_presets = preset_names(presets, "configurePresets");
+    configure_presets.extend_from_slice(included_configure_presets);
+    for list in PRESET_LISTS {
+        let mut seen = Vec::new();
+        for (i, preset) in presets_of(presets, list).iter().enumerate() {
+            let pointer = format!("/{}/{}", list, i);
+            let name = preset.get("name").and_then(Value::as_str);
+            match name {
+                Some(name) if seen.contains(&name) => problems.push(Problem::error(
+                    format!("Duplicate preset name {}", name),
+                    &format!("{}/name", pointer),
+                )),
+                Some(name) => seen.push(name),
+                None => problems.push(Problem::error(
+                    "Preset without a name".to_string(),
+                    &pointer,
+                )),
+            }
+
+            // It could be in an included file
+            if let Some(configure_preset) = preset.get("configurePreset").and_then(Value::as_str) {
+                if !configure_presets.contains(&configure_preset) && !obj.contains_key("include") {
+                    problems.push(Problem::warning(
+                        format!("Unknown configure preset {}", configure_preset),
+                        &format!("{}/configurePreset", pointer),
+                    ));
+                }
+            }
+        }
+    }
+
+    problems
+}
+
+fn presets_of<'a>(presets: &'a Value, list: &str) -> &'a [Value] {
+    presets
+        .get(list)
+        .and_then(Value::as_array)
+        .map_or(&[], Vec::as_slice)
+}
+
+/// The names of the presets in list, like "configurePresets"
+pub fn preset_names<'a>(presets: &'a Value, list: &str) -> Vec<&'a str> {
+    presets_of(presets, list)
+        .iter()
+        .filter_map(|preset| preset.get("name").and_then(Value::as_str))
+        .collect()
+}
+
+/// Returns the "cmake" global. source is the value of "gen.cmake_build_dir", the build directory,
+/// relative to base_dir. The global has the "build_dir" and "source_dir" CMake reports, and the
+/// "executables" of the first configuration, with their "name", "path" and "source_dir".
+/// Returns a message describing the problem on failure.
+pub fn global(source: &Value, base_dir: &Path) -> Result<Value, String> {
+    let Some(build_dir) = source.as_str() else {
+        return Err("Expected the path of a CMake build directory".to_string());
+    };
+
+    let reply_dir = base_dir.join(build_dir).join(FILE_API_DIR).join("reply");
+    let no_reply = || {
+        format!(
+            "No CMake File API reply in {}. Create an empty {}/query/codemodel-v2 file in the build directory and run cmake again",
+            reply_dir.display(),
+            FILE_API_DIR
+        )
+    };
+
+    let read = |filename: &str| -> Result<Value, String> {
+        let path = reply_dir.join(filename);
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|e| format!("Can't read {}: {}", path.display(), e))?;
+        serde_json::from_str(&contents)
+            .map_err(|e| format!("Can't parse {}: {}", path.display(), e))
+    };
+
+    // CMake writes a new index each time, and the newest sorts last
+    let index = std::fs::read_dir(&reply_dir)
+        .map_err(|_| no_reply())?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .filter(|name| name.starts_with("index-") && name.ends_with(".json"))
+        .max()
+        .ok_or_else(no_reply)?;
+
+    let codemodel_file = read(&index)?
+        .get("objects")
+        .and_then(Value::as_array)
+        .and_then(|objects| {
+            objects.iter().find(|object| {
+                object.get("kind").and_then(Value::as_str) == Some("codemodel")
+                    && object.pointer("/version/major").and_then(Value::as_u64) == Some(2)
+            })
+        })
+        .and_then(|object| object.get("jsonFile"))
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .ok_or_else(no_reply)?;
+
+    let codemodel = read(&codemodel_file)?;
+    let path_of = |value: &Value, name: &str| {
+        value
+            .pointer(&format!("/paths/{}", name))
+            .and_then(Value::as_str)
+            .unwrap_or(".")
+            .to_string()
+    };
+    let build_path = path_of(&codemodel, "build");
+    let source_path = path_of(&codemodel, "source");
+
+    let mut executables = Vec::new();
+    let targets = codemodel
+        .pointer("/configurations/0/targets")
+        .and_then(Value::as_array);
+    for target in targets.into_iter().flatten() {
+        let Some(target_file) = target.get("jsonFile").and_then(Value::as_str) else {
+            continue;
+        };
+        let target = read(target_file)?;
+        if target.get("type").and_then(Value::as_str) != Some("EXECUTABLE") {
+            continue;
+        }
+
+        // Artifact paths are relative to the top build directory, unless they're outside of it
+        let Some(artifact) = target.pointer("/artifacts/0/path").and_then(Value::as_str) else {
+            continue;
+        };
+
+        let mut executable = Map::new();
+        executable.insert("name".to_string(), target["name"].clone());
+        executable.insert(
+            "path".to_string(),
+            Value::String(join(&build_path, artifact)),
+        );
+        executable.insert(
+            "source_dir".to_string(),
+            Value::String(join(&source_path, &path_of(&target, "source"))),
+        );
+        executables.push(Value::Object(executable));
+    }
+
+    let mut global = Map::new();
+    global.insert("build_dir".to_string(), Value::String(build_path));
+    global.insert("source_dir".to_string(), Value::String(source_path));
+    global.insert("executables".to_string(), Value::Array(executables));
+    Ok(Value::Object(global))
+}
+
+/// Returns a VS Code task per visible preset of presets: "Configure <name>", "Build <name>"
+/// and "Test <name>". Build tasks get the problem matcher of their configure preset's compiler.
+pub fn tasks(presets: &[&Value], current_os: &str) -> Vec<Value> {
+    let presets_of_all = |list: &str| -> Vec<&Value> {
+        presets
+            .iter()
+            .flat_map(|presets| presets_of(presets, list))
+            .collect()
+    };
+    let configure_presets = presets_of_all("configurePresets");
+
+    let visible = |preset: &&Value| preset.get("hidden").and_then(Value::as_bool) != Some(true);
+    let name = |preset: &Value| {
+        preset
+            .get("name")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string()
+    };
+    let task = |label: String, command: &str, args: Vec<&str>, group: Option<&str>, matcher| {
+        let mut task = Map::new();
+        task.insert("label".to_string(), Value::String(label));
+        task.insert("type".to_string(), Value::String("shell".to_string()));
+        task.insert("command".to_string(), Value::String(command.to_string()));
+        task.insert("args".to_string(), serde_json::json!(args));
+        if let Some(group) = group {
+            task.insert("group".to_string(), Value::String(group.to_string()));
+        }
+        task.insert("problemMatcher".to_string(), matcher);
+        Value::Object(task)
+    };
+
+    let mut tasks = Vec::new();
+    for preset in configure_presets.iter().copied().filter(visible) {
+        let name = name(preset);
+        tasks.push(task(
+            format!("Configure {}", name),
+            "cmake",
+            vec!["--preset", &name],
+            None,
+            serde_json::json!([]),
+        ));
+    }
+
+    for preset in presets_of_all("buildPresets").into_iter().filter(visible) {
+        let name = name(preset);
+        let compiler = preset
+            .get("configurePreset")
+            .and_then(Value::as_str)
+            .and_then(|configure_preset| compiler(&configure_presets, configure_preset, 0));
+        let matcher = problem_matcher(compiler.as_deref(), current_os);
+        tasks.push(task(
+            format!("Build {}", name),
+            "cmake",
+            vec!["--build", "--preset", &name],
+            Some("build"),
+            serde_json::json!([matcher]),
+        ));
+    }
+
+    for preset in presets_of_all("testPresets").into_iter().filter(visible) {
+        let name = name(preset);
+        tasks.push(task(
+            format!("Test {}", name),
+            "ctest",
+            vec!["--preset", &name],
+            Some("test"),
+            serde_json::json!([]),
+        ));
+    }
+
+    tasks
+}
+
+/// The compiler a configure preset uses, from its CMAKE_CXX_COMPILER or CMAKE_C_COMPILER cache
+/// variables, or its generator. Looks in the presets it inherits from too.
+fn compiler(configure_presets: &[&Value], name: &str, depth: usize) -> Option<String> {
+    // Cyclic inheritance is for CMake to complain about
+    if depth > configure_presets.len() {
+        return None;
+    }
+
+    let preset = configure_presets
+        .iter()
+        .find(|preset| preset.get("name").and_then(Value::as_str) == Some(name))?;
+
+    for variable in ["CMAKE_CXX_COMPILER", "CMAKE_C_COMPILER"] {
+        // Either a string, or an object with a type and a value
+        let value = preset.pointer(&format!("/cacheVariables/{}", variable));
+        let value = value.and_then(|v| v.as_str().or_else(|| v.get("value")?.as_str()));
+        if let Some(value) = value {
+            return Some(value.to_string());
+        }
+    }
+
+    if let Some(generator) = preset.get("generator").and_then(Value::as_str) {
+        if generator.starts_with("Visual Studio") {
+            return Some("cl".to_string());
+        }
+    }
+
+    let inherits = match preset.get("inherits") {
+        Some(Value::String(base)) => vec![base.as_str()],
+        Some(Value::Array(bases)) => bases.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    };
+    inherits
+        .into_iter()
+        .find_map(|base| compiler(configure_presets, base, depth + 1))
+}
+
+/// The problem matcher of the C/C++ extension for compiler: "$msCompile" for MSVC and
+/// clang-cl, "$gcc" for GCC and Clang. Without a known compiler, the OS's usual one.
+fn problem_matcher(compiler: Option<&str>, current_os: &str) -> &'static str {
+    let program = compiler.map(|compiler| {
+        let filename = compiler.rsplit(['/', '\\']).next().unwrap_or(compiler);
+        filename
+            .trim_end_matches(".exe")
+            .trim_end_matches(".EXE")
+            .to_lowercase()
+    });
+
+    match program.as_deref() {
+        Some("cl") | Some("clang-cl") => "$msCompile",
+        Some(_) => "$gcc",
+        None if current_os == "windows" => "$msCompile",
+        None => "$gcc",
+    }
+}
+
+/// Joins CMake paths, which use forward slashes on every OS
+fn join(dir: &str, path: &str) -> String {
+    if path == "." {
+        dir.to_string()
+    } else if is_absolute(path) {
+        path.to_string()
+    } else {
+        format!("{}/{}", dir.trim_end_matches('/'), path)
+    }
+}
+
+/// Whether a CMake path is absolute, whichever OS the build directory comes from.
+/// Path::is_absolute() doesn't know about drive letters, like "C:/", on Linux.
+fn is_absolute(path: &str) -> bool {
+    let has_drive_letter = matches!(
+        path.as_bytes(),
+        [drive, b':', b'/' | b'\\', ..] if drive.is_ascii_alphabetic()
+    );
+
+    has_drive_letter || path.starts_with(['/', '\\']) || Path::new(path).is_absolute()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_join() {
+        assert_eq!(join("/ws/build", "bin/app"), "/ws/build/bin/app");
+        assert_eq!(join("/ws/build/", "."), "/ws/build/");
+        assert_eq!(join("/ws/build", "/opt/app"), "/opt/app");
+        assert_eq!(
+            join("C:/ws/build", "bin/app.exe"),
+            "C:/ws/build/bin/app.exe"
+        );
+        assert_eq!(join("C:/ws/build", "D:/out/app.exe"), "D:/out/app.exe");
+        assert_eq!(join("C:/ws/build", "d:\\out\\app.exe"), "d:\\out\\app.exe");
+        assert_eq!(join("C:/ws/build", "C:app.exe"), "C:/ws/build/C:app.exe");
+    }
+
+    fn messages(presets: Value) -> Vec<(String, String, bool)> {
+        check_presets(&presets, &[])
+            .into_iter()
+            .map(|p| (p.message, p.pointer, p.is_error))
+            .collect()
+    }
+
+    #[test]
+    fn test_problem_matcher() {
+        assert_eq!(problem_matcher(Some("/usr/bin/g++-13"), "linux"), "$gcc");
+        assert_eq!(problem_matcher(Some("clang++"), "windows"), "$gcc");
+        assert_eq!(
+            problem_matcher(Some("C:\\LLVM\\bin\\clang-cl.exe"), "windows"),
+            "$msCompile"
+        );
+        assert_eq!(problem_matcher(Some("cl"), "windows"), "$msCompile");
+        assert_eq!(problem_matcher(None, "windows"), "$msCompile");
+        assert_eq!(problem_matcher(None, "macos"), "$gcc");
+    }
+
+    #[test]
+    fn test_check_presets() {
+        let presets = json!({
+            "version": 3,
+            "configurePresets": [{ "name": "dev", "binaryDir": "build-dev" }],
+            "buildPresets": [{ "name": "dev", "configurePreset": "dev" }]
+        });
+        assert!(check_presets(&presets, &[]).is_empty());
+
+        // CMakeUserPresets.json can use the configure presets of CMakePresets.json
+        let user_presets = json!({
+            "version": 3,
+            "buildPresets": [{ "name": "mine", "configurePreset": "dev" }]
+        });
+        assert!(check_presets(&user_presets, &["dev"]).is_empty());
+        assert!(!check_presets(&user_presets, &[]).is_empty());
+
+        assert_eq!(
+            messages(json!({ "configurePresets": [] })),
+            vec![(
+                "Missing \"version\", the presets schema version".to_string(),
+                "".to_string(),
+                true
+            )]
+        );
+        assert_eq!(
+            messages(json!({ "version": "3" })),
+            vec![(
+                "Invalid version \"3\", expected a number from 1".to_string(),
+                "/version".to_string(),
+                true
+            )]
+        );
+        assert!(!check_presets(&json!({ "version": 99 }), &[])[0].is_error);
+
+        assert_eq!(
+            messages(json!({
+                "version": 1,
+                "configurePresets": [{ "name": "dev" }, { "name": "dev" }],
+                "buildPresets": [{ "name": "b", "configurePreset": "rel" }]
+            })),
+            vec![
+                (
+                    "\"buildPresets\" requires version 2 or later, but version is 1".to_string(),
+                    "/buildPresets".to_string(),
+                    true
+                ),
+                (
+                    "Duplicate preset name dev".to_string(),
+                    "/configurePresets/1/name".to_string(),
+                    true
+                ),
+                (
+                    "Unknown configure preset rel".to_string(),
+                    "/buildPresets/0/configurePreset".to_string(),
+                    false
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,302 @@
+// SPDX-License-Identifier: MIT
+
+// The expression language of "gen.if", for example:
+//     os == "linux" && env.QT_INSTALL && profile in ["dev", "ci"]
+// Operators, from lowest to highest precedence: ||, &&, !, and ==, != and in.
+// Strings use double or single quotes. Identifiers are resolved by the caller, and unknown
+// ones are null. null, false, "" and 0 are false, everything else is true.
+
+use serde_json::Value;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Identifier(String),
+    Literal(Value),
+    Eq,
+    Ne,
+    And,
+    Or,
+    Not,
+    In,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Comma,
+}
+
+/// Evaluates expression. lookup resolves identifiers, like "os" or "env.HOME".
+/// Returns a message describing the problem if the expression is malformed.
+pub fn evaluate(expression: &str, lookup: &dyn Fn(&str) -> Option<Value>) -> Result<bool, String> {
+    let mut parser = Parser {
+        tokens: tokenize(expression)?,
+        pos: 0,
+        lookup,
+    };
+
+    let value = parser.or()?;
+    if let Some((_, text)) = parser.tokens.get(parser.pos) {
+        return Err(format!("Unexpected `{}`", text));
+    }
+
+    Ok(is_true(&value))
+}
+
+fn is_true(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::String(s) => !s.is_empty(),
+        Value::Number(n) => n.as_f64() != Some(0.0),
+        Value::Array(_) | Value::Object(_) => true,
+    }
+}
+
+/// Scalars are compared by their text, so 1 == "1"
+fn equals(a: &Value, b: &Value) -> bool {
+    let text = |v: &Value| match v {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    };
+
+    match (a, b) {
+        (Value::Null, _) | (_, Value::Null) => a == b,
+        _ => text(a) == text(b),
+    }
+}
+
+/// Returns each token, and the text it was made from
+fn tokenize(expression: &str) -> Result<Vec<(Token, String)>, String> {
+    let chars: Vec<char> = expression.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        let two: String = chars[i..(i + 2).min(chars.len())].iter().collect();
+
+        let token = match (c, two.as_str()) {
+            (c, _) if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            (_, "==") => Token::Eq,
+            (_, "!=") => Token::Ne,
+            (_, "&&") => Token::And,
+            (_, "||") => Token::Or,
+            ('!', _) => Token::Not,
+            ('(', _) => Token::LParen,
+            (')', _) => Token::RParen,
+            ('[', _) => Token::LBracket,
+            (']', _) => Token::RBracket,
+            (',', _) => Token::Comma,
+            ('"' | '\'', _) => {
+                let mut s = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        None => return Err("Unterminated string".to_string()),
+                        Some('\\') => {
+                            s.extend(chars.get(i + 1));
+                            i += 2;
+                        }
+                        Some(&q) if q == c => break,
+                        Some(&other) => {
+                            s.push(other);
+                            i += 1;
+                        }
+                    }
+                }
+                Token::Literal(Value::String(s))
+            }
+            (c, _) if c.is_ascii_digit() => {
+                while i + 1 < chars.len() && (chars[i + 1].is_ascii_digit() || chars[i + 1] == '.')
+                {
+                    i += 1;
+                }
+                let text: String = chars[start..=i].iter().collect();
+                let number = serde_json::from_str(&text)
+                    .map_err(|_| format!("Invalid number `{}`", text))?;
+                Token::Literal(number)
+            }
+            (c, _) if c.is_ascii_alphabetic() || c == '_' => {
+                while i + 1 < chars.len()
+                    && (chars[i + 1].is_ascii_alphanumeric() || matches!(chars[i + 1], '_' | '.'))
+                {
+                    i += 1;
+                }
+                let word: String = chars[start..=i].iter().collect();
+                match word.as_str() {
+                    "true" => Token::Literal(Value::Bool(true)),
+                    "false" => Token::Literal(Value::Bool(false)),
+                    "null" => Token::Literal(Value::Null),
+                    "in" => Token::In,
+                    _ => Token::Identifier(word),
+                }
+            }
+            (c, _) => return Err(format!("Unexpected character `{}`", c)),
+        };
+
+        i += match token {
+            Token::Eq | Token::Ne | Token::And | Token::Or => 2,
+            _ => 1,
+        };
+        tokens.push((token, chars[start..i].iter().collect()));
+    }
+
+    Ok(tokens)
+}
+
+/// A recursive descent parser, which evaluates as it goes
+struct Parser<'a> {
+    tokens: Vec<(Token, String)>,
+    pos: usize,
+    lookup: &'a dyn Fn(&str) -> Option<Value>,
+}
+
+impl Parser<'_> {
+    /// Consumes the next token if it's token
+    fn eat(&mut self, token: &Token) -> bool {
+        let matches = self.tokens.get(self.pos).is_some_and(|(t, _)| t == token);
+        if matches {
+            self.pos += 1;
+        }
+        matches
+    }
+
+    fn expect(&mut self, token: &Token, text: &str) -> Result<(), String> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(format!("Expected `{}`", text))
+        }
+    }
+
+    fn or(&mut self) -> Result<Value, String> {
+        let mut value = self.and()?;
+        while self.eat(&Token::Or) {
+            let rhs = self.and()?;
+            value = Value::Bool(is_true(&value) || is_true(&rhs));
+        }
+        Ok(value)
+    }
+
+    fn and(&mut self) -> Result<Value, String> {
+        let mut value = self.not()?;
+        while self.eat(&Token::And) {
+            let rhs = self.not()?;
+            value = Value::Bool(is_true(&value) && is_true(&rhs));
+        }
+        Ok(value)
+    }
+
+    fn not(&mut self) -> Result<Value, String> {
+        if self.eat(&Token::Not) {
+            let value = self.not()?;
+            return Ok(Value::Bool(!is_true(&value)));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Value, String> {
+        let lhs = self.primary()?;
+        if self.eat(&Token::Eq) {
+            Ok(Value::Bool(equals(&lhs, &self.primary()?)))
+        } else if self.eat(&Token::Ne) {
+            Ok(Value::Bool(!equals(&lhs, &self.primary()?)))
+        } else if self.eat(&Token::In) {
+            let list = self.list()?;
+            Ok(Value::Bool(list.iter().any(|v| equals(&lhs, v))))
+        } else {
+            Ok(lhs)
+        }
+    }
+
+    fn list(&mut self) -> Result<Vec<Value>, String> {
+        self.expect(&Token::LBracket, "[")?;
+        let mut list = Vec::new();
+        if self.eat(&Token::RBracket) {
+            return Ok(list);
+        }
+
+        loop {
+            list.push(self.primary()?);
+            if !self.eat(&Token::Comma) {
+                self.expect(&Token::RBracket, "]")?;
+                return Ok(list);
+            }
+        }
+    }
+
+    fn primary(&mut self) -> Result<Value, String> {
+        let Some((token, text)) = self.tokens.get(self.pos).cloned() else {
+            return Err("Unexpected end of expression".to_string());
+        };
+        self.pos += 1;
+
+        match token {
+            Token::LParen => {
+                let value = self.or()?;
+                self.expect(&Token::RParen, ")")?;
+                Ok(value)
+            }
+            Token::Literal(value) => Ok(value),
+            Token::Identifier(name) => Ok((self.lookup)(&name).unwrap_or(Value::Null)),
+            _ => Err(format!("Unexpected `{}`", text)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<Value> {
+        match name {
+            "os" => Some(Value::String("linux".to_string())),
+            "env.QT_INSTALL" => Some(Value::String("/opt/Qt".to_string())),
+            "profile" => Some(Value::String("ci".to_string())),
+            "jobs" => Some(Value::from(4)),
+            _ => None,
+        }
+    }
+
+    fn eval(expression: &str) -> Result<bool, String> {
+        evaluate(expression, &lookup)
+    }
+
+    #[test]
+    fn test_evaluate() {
+        assert_eq!(
+            eval(r#"os == "linux" && env.QT_INSTALL && profile in ["dev", "ci"]"#),
+            Ok(true)
+        );
+        assert_eq!(eval("os == 'windows' || env.UNSET"), Ok(false));
+        assert_eq!(eval("!env.UNSET && os != 'macos'"), Ok(true));
+        assert_eq!(eval("!(os == 'linux' || false)"), Ok(false));
+        assert_eq!(eval("profile in []"), Ok(false));
+        assert_eq!(eval("jobs == 4 && jobs == '4' && !(jobs == 5)"), Ok(true));
+        assert_eq!(eval("unknown == null"), Ok(true));
+        assert_eq!(eval("true || false && false"), Ok(true));
+        assert_eq!(eval(r#"'it\'s' == "it's""#), Ok(true));
+        assert_eq!(eval("0"), Ok(false));
+    }
+
+    #[test]
+    fn test_evaluate_errors() {
+        assert_eq!(
+            eval("os =="),
+            Err("Unexpected end of expression".to_string())
+        );
+        assert_eq!(eval("os == 'linux"), Err("Unterminated string".to_string()));
+        assert_eq!(
+            eval("os = 'linux'"),
+            Err("Unexpected character `=`".to_string())
+        );
+        assert_eq!(eval("(os"), Err("Expected `)`".to_string()));
+        assert_eq!(eval("os in 'linux'"), Err("Expected `[`".to_string()));
+        assert_eq!(eval("os linux"), Err("Unexpected `linux`".to_string()));
+        assert_eq!(eval("&& os"), Err("Unexpected `&&`".to_string()));
+    }
+}
@@ -2,9 +2,13 @@
 
 // Represents the contents of a .vscode-workspace-gen config file
 
+use std::collections::BTreeMap;
+
 use serde::Deserialize;
 
-#[derive(Debug, Deserialize)]
+use crate::jsonc;
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct Config {
     #[serde(default)]
     json_indent: u32,
@@ -13,22 +17,75 @@ pub struct Config {
 
     #[serde(default)]
     per_os_output_filenames: Option<PerOsOutputFileNames>,
+
+    /// Unresolved references are errors instead of warnings
+    #[serde(default)]
+    strict: bool,
+
+    /// Comments in the template are written to the generated file
+    #[serde(default)]
+    preserve_comments: bool,
+
+    /// Values which override the template's globals. -D has priority.
+    #[serde(default)]
+    variables: serde_json::Map<String, serde_json::Value>,
+
+    /// Only generate for these OSes. All of them if unset.
+    #[serde(default)]
+    os_targets: Option<Vec<String>>,
+
+    /// Names for sets of OSes, usable in "gen.os". Extends, or overrides, the built-in ones.
+    #[serde(default)]
+    os_groups: BTreeMap<String, Vec<String>>,
+
+    /// Where "gen.cmakepresets" is written. CMakePresets.json if unset.
+    cmake_presets_filename: Option<String>,
+
+    /// Where "gen.cmakeuserpresets" is written. CMakeUserPresets.json if unset.
+    cmake_user_presets_filename: Option<String>,
+
+    /// Named variants, selected with --profile or --all-profiles
+    #[serde(default)]
+    profiles: BTreeMap<String, Profile>,
+
+    /// The profile this config was made for, by with_profile()
+    #[serde(skip)]
+    profile: Option<String>,
 }
 
-/// Allows to generate output for different OSes. Can generate 3 files at once.
-#[derive(Debug, Deserialize)]
-pub struct PerOsOutputFileNames {
-    windows: Option<String>,
-    linux: Option<String>,
-    macos: Option<String>,
+/// A named set of variables and outputs, which override the ones at the config's top level
+#[derive(Debug, Clone, Deserialize)]
+pub struct Profile {
+    #[serde(default)]
+    variables: serde_json::Map<String, serde_json::Value>,
+
+    output_filename: Option<String>,
+
+    per_os_output_filenames: Option<PerOsOutputFileNames>,
+
+    os_targets: Option<Vec<String>>,
 }
 
+/// Allows to generate output for different OSes, by OS name, for example "freebsd".
+/// The names are the ones "gen.os" accepts, and a null filename skips the OS.
+/// Outputs are generated in the order of the config file.
+pub type PerOsOutputFileNames = serde_json::Map<String, serde_json::Value>;
+
 impl Default for Config {
     fn default() -> Self {
         Self {
             json_indent: 2,
             output_filename: None,
             per_os_output_filenames: None,
+            strict: false,
+            preserve_comments: false,
+            variables: serde_json::Map::new(),
+            os_targets: None,
+            os_groups: BTreeMap::new(),
+            cmake_presets_filename: None,
+            cmake_user_presets_filename: None,
+            profiles: BTreeMap::new(),
+            profile: None,
         }
     }
 }
@@ -36,7 +93,7 @@ impl Default for Config {
 impl Config {
     pub fn from_file(filename: &str) -> Result<Self, std::io::Error> {
         let contents = std::fs::read_to_string(filename)?;
-        let conf: Config = serde_json::from_str(&contents)?;
+        let conf: Config = serde_json::from_str(&jsonc::strip(&contents))?;
 
         Ok(conf)
     }
@@ -56,12 +113,98 @@ impl Config {
         }
     }
 
+    pub fn strict(&self) -> bool {
+        self.strict
+    }
+
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    pub fn preserve_comments(&self) -> bool {
+        self.preserve_comments
+    }
+
+    pub fn variables(&self) -> &serde_json::Map<String, serde_json::Value> {
+        &self.variables
+    }
+
+    pub fn set_variable(&mut self, name: String, value: serde_json::Value) {
+        self.variables.insert(name, value);
+    }
+
+    pub fn cmake_presets_filename(&self) -> &str {
+        self.cmake_presets_filename
+            .as_deref()
+            .unwrap_or("CMakePresets.json")
+    }
+
+    pub fn cmake_user_presets_filename(&self) -> &str {
+        self.cmake_user_presets_filename
+            .as_deref()
+            .unwrap_or("CMakeUserPresets.json")
+    }
+
+    pub fn profile_names(&self) -> Vec<&String> {
+        self.profiles.keys().collect()
+    }
+
+    /// The profile this config was made for, if any
+    pub fn profile(&self) -> Option<&str> {
+        self.profile.as_deref()
+    }
+
+    /// Returns this config with the profile's settings applied.
+    /// The profile's name is available to templates as the "profile" variable.
+    pub fn with_profile(&self, name: &str) -> Result<Config, String> {
+        let Some(profile) = self.profiles.get(name) else {
+            let names: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+            return Err(format!(
+                "Unknown profile {}. Available profiles: {}",
+                name,
+                names.join(", ")
+            ));
+        };
+
+        let mut config = self.clone();
+        config.profile = Some(name.to_string());
+        config.set_variable(
+            "profile".to_string(),
+            serde_json::Value::String(name.to_string()),
+        );
+        config.variables.extend(profile.variables.clone());
+
+        if profile.output_filename.is_some() || profile.per_os_output_filenames.is_some() {
+            config.output_filename = profile.output_filename.clone();
+            config.per_os_output_filenames = profile.per_os_output_filenames.clone();
+        }
+
+        if profile.os_targets.is_some() {
+            config.os_targets = profile.os_targets.clone();
+        }
+
+        Ok(config)
+    }
+
     pub fn is_valid(&self) -> Result<(), String> {
         if self.output_filename.is_some() && self.per_os_output_filenames.is_some() {
-            Err("Only one of output_filename or per_os_output_filenames can be set".to_string())
-        } else {
-            Ok(())
+            return Err(
+                "Only one of output_filename or per_os_output_filenames can be set".to_string(),
+            );
+        }
+
+        let per_os_output = self.per_os_output_filenames.iter().flatten();
+        if let Some((os, _)) = per_os_output
+            .into_iter()
+            .find(|(_, filename)| !filename.is_string() && !filename.is_null())
+        {
+            return Err(format!(
+                "per_os_output_filenames: expected a filename or null for {}",
+                os
+            ));
         }
+
+        Ok(())
     }
 
     pub fn has_output(&self) -> bool {
@@ -70,29 +213,76 @@ impl Config {
 
     // returns a list of tuples with the OS and the output filename
     // else returns current OS
+    // Only OSes in os_targets are returned
     pub fn outputs(&self) -> Option<Vec<(&str, &String)>> {
-        match &self.per_os_output_filenames {
-            Some(per_os_output) => {
-                let mut targets = Vec::new();
-                if let Some(windows) = &per_os_output.windows {
-                    targets.push(("windows", windows));
-                }
-                if let Some(linux) = &per_os_output.linux {
-                    targets.push(("linux", linux));
-                }
-                if let Some(macos) = &per_os_output.macos {
-                    targets.push(("macos", macos));
-                }
-                Some(targets)
-            }
+        let outputs = match &self.per_os_output_filenames {
+            Some(per_os_output) => Some(per_os_outputs(per_os_output).collect()),
             None => self
                 .output_filename
                 .as_ref()
                 .map(|output_filename| vec![(std::env::consts::OS, output_filename)]),
+        };
+
+        outputs.map(|outputs| {
+            outputs
+                .into_iter()
+                .filter(|(os, _)| self.is_os_targeted(os))
+                .collect()
+        })
+    }
+
+    /// The OSes the per OS outputs are generated for.
+    /// None if there are no per OS outputs, in which case we generate for the current OS.
+    pub fn target_oses(&self) -> Option<Vec<&str>> {
+        self.per_os_output_filenames.as_ref().map(|per_os_output| {
+            per_os_outputs(per_os_output)
+                .map(|(os, _)| os)
+                .filter(|os| self.is_os_targeted(os))
+                .collect()
+        })
+    }
+
+    /// The OSes the config mentions, in per OS outputs and os_targets
+    pub fn configured_oses(&self) -> Vec<&str> {
+        self.per_os_output_filenames
+            .iter()
+            .flat_map(|per_os_output| per_os_outputs(per_os_output).map(|(os, _)| os))
+            .chain(self.os_targets.iter().flatten().map(String::as_str))
+            .collect()
+    }
+
+    /// The built-in OS groups, plus the ones from the config file
+    pub fn os_groups(&self) -> BTreeMap<String, Vec<String>> {
+        let mut os_groups = BTreeMap::from([(
+            "unix".to_string(),
+            vec![
+                "linux".to_string(),
+                "macos".to_string(),
+                "freebsd".to_string(),
+            ],
+        )]);
+        os_groups.extend(self.os_groups.clone());
+        os_groups
+    }
+
+    fn is_os_targeted(&self, os: &str) -> bool {
+        match &self.os_targets {
+            Some(os_targets) => os_targets.iter().any(|o| o == os),
+            None => true,
         }
     }
 
-    fn filename() -> &'static str {
+    pub fn filename() -> &'static str {
         ".vscode-workspace-gen.json"
     }
 }
+
+/// The OSes and filenames of per OS outputs, skipping the null ones
+fn per_os_outputs(per_os_output: &PerOsOutputFileNames) -> impl Iterator<Item = (&str, &String)> {
+    per_os_output
+        .iter()
+        .filter_map(|(os, filename)| match filename {
+            serde_json::Value::String(filename) => Some((os.as_str(), filename)),
+            _ => None,
+        })
+}
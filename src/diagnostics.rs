@@ -0,0 +1,515 @@
+// SPDX-License-Identifier: MIT
+
+// Warnings and errors found while expanding a template.
+// Each one knows the JSON pointer where it happened, which we map back to a line and column
+// in the template, so we can print rustc-style messages with a snippet of the source.
+
+use std::fmt::Write;
+use std::path::Path;
+
+use crate::include;
+use crate::jsonc;
+use crate::workspace::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+
+    /// Where in the template the problem was found, for example "/launch/configurations/3".
+    /// It can be in a file the template includes too, see render_in_origin().
+    pub json_pointer: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn warning(message: String, json_pointer: &str) -> Self {
+        Self {
+            severity: Severity::Warning,
+            message,
+            json_pointer: Some(json_pointer.to_string()),
+        }
+    }
+
+    pub fn error(message: String, json_pointer: Option<&str>) -> Self {
+        Self {
+            severity: Severity::Error,
+            message,
+            json_pointer: json_pointer.map(str::to_string),
+        }
+    }
+
+    /// Formats the diagnostic. If the template's source is passed, includes a snippet of it.
+    pub fn render(&self, filename: &str, source: Option<&str>) -> String {
+        let mut result = String::new();
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        writeln!(result, "{}: {}", severity, self.message).unwrap();
+
+        let Some(pointer) = &self.json_pointer else {
+            writeln!(result, "  --> {}", filename).unwrap();
+            return result;
+        };
+
+        let location = source.and_then(|source| locate(source, pointer).map(|l| (source, l)));
+        let Some((source, location)) = location else {
+            writeln!(result, "  --> {}", filename).unwrap();
+            writeln!(result, "   = at {}", pointer).unwrap();
+            return result;
+        };
+
+        let line = source.lines().nth(location.line - 1).unwrap_or_default();
+        let line_number = location.line.to_string();
+        let gutter = " ".repeat(line_number.len());
+
+        // Keep tabs, so the underline aligns with the source line
+        let padding: String = line
+            .chars()
+            .take(location.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+
+        writeln!(
+            result,
+            "{}--> {}:{}:{}",
+            gutter, filename, location.line, location.column
+        )
+        .unwrap();
+        writeln!(result, "{} |", gutter).unwrap();
+        writeln!(result, "{} | {}", line_number, line).unwrap();
+        writeln!(
+            result,
+            "{} | {}{}",
+            gutter,
+            padding,
+            "^".repeat(location.len.max(1))
+        )
+        .unwrap();
+        writeln!(result, "{} = at {}", gutter, pointer).unwrap();
+
+        result
+    }
+}
+
+/// The diagnostics collected while generating
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    entries: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub fn push(&mut self, diagnostic: Diagnostic) {
+        self.entries.push(diagnostic);
+    }
+
+    pub fn append(&mut self, other: &mut Diagnostics) {
+        self.entries.append(&mut other.entries);
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Diagnostic> {
+        self.entries.iter()
+    }
+
+    /// Prints all diagnostics to stderr
+    pub fn print(&self, filename: &str, source: Option<&str>) {
+        for diagnostic in self.iter() {
+            eprintln!("{}", render_in_origin(diagnostic, filename, source));
+        }
+    }
+}
+
+/// Renders the diagnostic with a snippet of the file its content came from. That's the template
+/// if the pointer is in it, as the template has priority over the files it includes, or else the
+/// first included file which has it.
+fn render_in_origin(diagnostic: &Diagnostic, filename: &str, source: Option<&str>) -> String {
+    let in_template = match (&diagnostic.json_pointer, source) {
+        (Some(pointer), Some(source)) => locate(source, pointer).is_some(),
+        _ => true,
+    };
+
+    if !in_template {
+        let pointer = diagnostic.json_pointer.as_deref().unwrap();
+        for included in include::included_files(Path::new(filename)) {
+            let Ok(contents) = std::fs::read_to_string(&included) else {
+                continue;
+            };
+            if locate(&contents, pointer).is_some() {
+                return diagnostic.render(&included.display().to_string(), Some(&contents));
+            }
+        }
+    }
+
+    diagnostic.render(filename, source)
+}
+
+/// Prints an error to stderr, with a snippet of the file it happened in, if possible
+pub fn print_error(error: &Error, template_filename: &str) {
+    // Errors inside included files are located in the included file
+    let mut filename = template_filename;
+    let mut error = error;
+    while let Error::Include {
+        filename: included_filename,
+        error: included_error,
+    } = error
+    {
+        filename = included_filename;
+        error = included_error;
+    }
+
+    let source = std::fs::read_to_string(filename).ok();
+    let diagnostic = Diagnostic::error(error.to_string(), error.json_pointer());
+    eprintln!(
+        "{}",
+        render_in_origin(&diagnostic, filename, source.as_deref())
+    );
+}
+
+/// A position in the template source. line and column are 1-based, column counts characters.
+#[derive(Debug, PartialEq)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+
+    /// How many characters to underline
+    pub len: usize,
+}
+
+/// Finds where the value pointed to by json_pointer is in the JSON, or JSONC, source.
+/// Object members are located by their key, and scalars are underlined up to their value's end,
+/// if it's on the same line.
+pub fn locate(source: &str, json_pointer: &str) -> Option<Location> {
+    let tokens: Vec<String> = json_pointer
+        .split('/')
+        .skip(1)
+        .map(|t| t.replace("~1", "/").replace("~0", "~"))
+        .collect();
+
+    // Comments are blanked out, which keeps positions
+    let stripped = jsonc::strip(source);
+    let mut scanner = Scanner {
+        bytes: stripped.as_bytes(),
+        pos: 0,
+    };
+
+    let (start, end) = scanner.find(&tokens)?;
+
+    let line_start = source[..start].rfind('\n').map(|p| p + 1).unwrap_or(0);
+    let line = source[..start].matches('\n').count() + 1;
+    let column = source[line_start..start].chars().count() + 1;
+    let len = match source[start..end].find('\n') {
+        Some(_) => 1,
+        None => source[start..end].chars().count(),
+    };
+
+    Some(Location { line, column, len })
+}
+
+/// A minimal JSON scanner which only tracks positions
+struct Scanner<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Scanner<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek() {
+            if c.is_ascii_whitespace() {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Returns the start and end byte offsets of the value at path, relative to the current position
+    fn find(&mut self, path: &[String]) -> Option<(usize, usize)> {
+        self.skip_whitespace();
+        if path.is_empty() {
+            let start = self.pos;
+            self.skip_value()?;
+            return Some((start, self.pos));
+        }
+
+        match self.peek()? {
+            b'{' => {
+                self.pos += 1;
+                loop {
+                    self.skip_whitespace();
+                    if self.peek()? == b'}' {
+                        return None;
+                    }
+
+                    let key_start = self.pos;
+                    let key = self.string()?;
+                    let key_end = self.pos;
+                    self.skip_whitespace();
+                    if self.peek()? != b':' {
+                        return None;
+                    }
+                    self.pos += 1;
+
+                    if key == path[0] {
+                        if path.len() > 1 {
+                            return self.find(&path[1..]);
+                        }
+
+                        self.skip_whitespace();
+                        let is_scalar = !matches!(self.peek()?, b'{' | b'[');
+                        self.skip_value()?;
+                        return Some((key_start, if is_scalar { self.pos } else { key_end }));
+                    }
+
+                    self.skip_value()?;
+                    if !self.separator(b'}')? {
+                        return None;
+                    }
+                }
+            }
+            b'[' => {
+                let index: usize = path[0].parse().ok()?;
+                self.pos += 1;
+                let mut i = 0;
+                loop {
+                    self.skip_whitespace();
+                    if self.peek()? == b']' {
+                        return None;
+                    }
+
+                    if i == index {
+                        return self.find(&path[1..]);
+                    }
+
+                    self.skip_value()?;
+                    if !self.separator(b']')? {
+                        return None;
+                    }
+                    i += 1;
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Consumes a ',' and returns true, or returns false if the container ends
+    fn separator(&mut self, close: u8) -> Option<bool> {
+        self.skip_whitespace();
+        match self.peek()? {
+            b',' => {
+                self.pos += 1;
+                Some(true)
+            }
+            c if c == close => Some(false),
+            _ => None,
+        }
+    }
+
+    /// Consumes a string and returns it unescaped
+    fn string(&mut self) -> Option<String> {
+        let start = self.pos;
+        self.skip_string()?;
+        let raw = std::str::from_utf8(&self.bytes[start..self.pos]).ok()?;
+        serde_json::from_str(raw).ok()
+    }
+
+    fn skip_string(&mut self) -> Option<()> {
+        if self.peek()? != b'"' {
+            return None;
+        }
+        self.pos += 1;
+
+        loop {
+            match self.peek()? {
+                b'\\' => self.pos += 2,
+                b'"' => {
+                    self.pos += 1;
+                    return Some(());
+                }
+                _ => self.pos += 1,
+            }
+        }
+    }
+
+    fn skip_value(&mut self) -> Option<()> {
+        self.skip_whitespace();
+        match self.peek()? {
+            b'"' => self.skip_string(),
+            open @ (b'{' | b'[') => {
+                let close = if open == b'{' { b'}' } else { b']' };
+                self.pos += 1;
+                loop {
+                    self.skip_whitespace();
+                    if self.peek()? == close {
+                        self.pos += 1;
+                        return Some(());
+                    }
+
+                    if open == b'{' {
+                        self.skip_string()?;
+                        self.skip_whitespace();
+                        if self.peek()? != b':' {
+                            return None;
+                        }
+                        self.pos += 1;
+                    }
+
+                    self.skip_value()?;
+                    if !self.separator(close)? {
+                        self.pos += 1;
+                        return Some(());
+                    }
+                }
+            }
+            _ => {
+                // numbers, true, false, null
+                while let Some(c) = self.peek() {
+                    if c.is_ascii_alphanumeric() || matches!(c, b'-' | b'+' | b'.') {
+                        self.pos += 1;
+                    } else {
+                        break;
+                    }
+                }
+                Some(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = r#"{
+    "launch": {
+        "configurations": [
+            { "name": "a" },
+            {
+                "name": "b",
+                "cargo": { "filter": "@{filter}" }
+            }
+        ]
+    },
+    "a/b": [1, 2, [3]]
+}"#;
+
+    #[test]
+    fn test_locate() {
+        assert_eq!(
+            locate(SOURCE, "/launch/configurations/1/cargo/filter"),
+            Some(Location {
+                line: 7,
+                column: 28,
+                len: 21
+            })
+        );
+
+        // Containers only underline the key
+        assert_eq!(
+            locate(SOURCE, "/launch/configurations"),
+            Some(Location {
+                line: 3,
+                column: 9,
+                len: 16
+            })
+        );
+
+        assert_eq!(
+            locate(SOURCE, "/launch/configurations/0"),
+            Some(Location {
+                line: 4,
+                column: 13,
+                len: 15
+            })
+        );
+
+        assert_eq!(
+            locate(SOURCE, "/a~1b/2/0"),
+            Some(Location {
+                line: 11,
+                column: 20,
+                len: 1
+            })
+        );
+
+        assert_eq!(locate(SOURCE, "/launch/unknown"), None);
+        assert_eq!(locate(SOURCE, "/a~1b/3"), None);
+        assert_eq!(locate(SOURCE, "/a~1b/0/0"), None);
+    }
+
+    #[test]
+    fn test_render() {
+        let diagnostic = Diagnostic::warning(
+            "No replacement found for key: filter".to_string(),
+            "/launch/configurations/1/cargo/filter",
+        );
+
+        let expected = r#"warning: No replacement found for key: filter
+ --> template.json:7:28
+  |
+7 |                 "cargo": { "filter": "@{filter}" }
+  |                            ^^^^^^^^^^^^^^^^^^^^^
+  = at /launch/configurations/1/cargo/filter
+"#;
+        assert_eq!(diagnostic.render("template.json", Some(SOURCE)), expected);
+
+        let expected = r#"warning: No replacement found for key: filter
+  --> template.json
+   = at /launch/configurations/1/cargo/filter
+"#;
+        assert_eq!(diagnostic.render("template.json", None), expected);
+    }
+
+    #[test]
+    fn test_render_in_origin() {
+        let dir = std::env::temp_dir().join(format!(
+            "vscode-workspace-gen-origin-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let template = dir.join("template.json");
+        let template_source = r#"{
+    "gen.include": "included.json",
+    "settings": { "a": 1 }
+}"#;
+        std::fs::write(&template, template_source).unwrap();
+        std::fs::write(
+            dir.join("included.json"),
+            r#"{
+    "settings": {
+        "b": "@{missing}"
+    }
+}"#,
+        )
+        .unwrap();
+
+        let filename = template.to_str().unwrap();
+        let render = |pointer: &str| {
+            let diagnostic = Diagnostic::warning("message".to_string(), pointer);
+            render_in_origin(&diagnostic, filename, Some(template_source))
+        };
+
+        // The included file's content is shown with its own lines
+        let rendered = render("/settings/b");
+        assert!(rendered.contains("included.json:3:9"));
+        assert!(rendered.contains(r#"3 |         "b": "@{missing}""#));
+
+        assert!(render("/settings/a").contains("template.json:3:19"));
+
+        // Neither has it
+        let rendered = render("/settings/c");
+        assert!(rendered.contains(&format!("  --> {}\n", filename)));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,203 @@
+// SPDX-License-Identifier: MIT
+
+// A small line based unified diff, to show why a generated file is out of date
+
+use std::fmt::Write;
+
+/// Lines of context around each change
+const CONTEXT: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// Returns the unified diff between old and new, or an empty string if they're equal
+pub fn unified_diff(old: &str, new: &str, old_name: &str, new_name: &str) -> String {
+    if old == new {
+        return String::new();
+    }
+
+    let old_lines: Vec<&str> = old.split('\n').collect();
+    let new_lines: Vec<&str> = new.split('\n').collect();
+    let ops = edit_script(&old_lines, &new_lines);
+
+    let mut result = String::new();
+    writeln!(result, "--- {}", old_name).unwrap();
+    writeln!(result, "+++ {}", new_name).unwrap();
+
+    // Positions in old and new where each op starts
+    let mut positions = Vec::with_capacity(ops.len() + 1);
+    let (mut i, mut j) = (0, 0);
+    for op in &ops {
+        positions.push((i, j));
+        match op {
+            Op::Equal => {
+                i += 1;
+                j += 1;
+            }
+            Op::Delete => i += 1,
+            Op::Insert => j += 1,
+        }
+    }
+    positions.push((i, j));
+
+    let mut k = 0;
+    while k < ops.len() {
+        if ops[k] == Op::Equal {
+            k += 1;
+            continue;
+        }
+
+        // A hunk starts with some context and extends while changes are close to each other
+        let start = k.saturating_sub(CONTEXT);
+        let mut end = k;
+        let mut equal_run = 0;
+        while end < ops.len() && equal_run <= 2 * CONTEXT {
+            if ops[end] == Op::Equal {
+                equal_run += 1;
+            } else {
+                equal_run = 0;
+            }
+            end += 1;
+        }
+        // Trim trailing context to CONTEXT lines
+        if equal_run > CONTEXT {
+            end -= equal_run - CONTEXT;
+        }
+
+        let (old_start, new_start) = positions[start];
+        let (old_end, new_end) = positions[end];
+        writeln!(
+            result,
+            "@@ -{} +{} @@",
+            hunk_range(old_start, old_end - old_start),
+            hunk_range(new_start, new_end - new_start)
+        )
+        .unwrap();
+
+        for (op, &(i, j)) in ops[start..end].iter().zip(&positions[start..end]) {
+            match op {
+                Op::Equal => writeln!(result, " {}", old_lines[i]).unwrap(),
+                Op::Delete => writeln!(result, "-{}", old_lines[i]).unwrap(),
+                Op::Insert => writeln!(result, "+{}", new_lines[j]).unwrap(),
+            }
+        }
+
+        k = end;
+    }
+
+    result
+}
+
+fn hunk_range(start: usize, len: usize) -> String {
+    match len {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, len),
+    }
+}
+
+/// Computes the shortest edit script using the longest common subsequence
+fn edit_script(old: &[&str], new: &[&str]) -> Vec<Op> {
+    // Common prefix and suffix don't need the quadratic part
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+    let (n, m) = (old_mid.len(), new_mid.len());
+
+    // lcs[i][j] is the LCS length of old_mid[i..] and new_mid[j..]
+    let mut lcs = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if old_mid[i] == new_mid[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut ops = vec![Op::Equal; prefix];
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && old_mid[i] == new_mid[j] {
+            ops.push(Op::Equal);
+            i += 1;
+            j += 1;
+        } else if i < n && (j == m || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            ops.push(Op::Delete);
+            i += 1;
+        } else {
+            ops.push(Op::Insert);
+            j += 1;
+        }
+    }
+    ops.extend(std::iter::repeat_n(Op::Equal, suffix));
+
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unified_diff() {
+        assert_eq!(unified_diff("a\nb", "a\nb", "old", "new"), "");
+
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12";
+        let new = "1\n2\n3\n4\nfive\n6\n7\n8\n9\n10\n11\n12\n13";
+        let expected = "--- old
++++ new
+@@ -2,7 +2,7 @@
+ 2
+ 3
+ 4
+-5
++five
+ 6
+ 7
+ 8
+@@ -10,3 +10,4 @@
+ 10
+ 11
+ 12
++13
+";
+        assert_eq!(unified_diff(old, new, "old", "new"), expected);
+
+        // Close changes share a hunk
+        let old = "a\nb\nc\nd\ne";
+        let new = "A\nb\nc\nd\nE";
+        let expected = "--- old
++++ new
+@@ -1,5 +1,5 @@
+-a
++A
+ b
+ c
+ d
+-e
++E
+";
+        assert_eq!(unified_diff(old, new, "old", "new"), expected);
+
+        let expected = "--- old
++++ new
+@@ -1 +1 @@
+-a
++b
+";
+        assert_eq!(unified_diff("a", "b", "old", "new"), expected);
+    }
+}
@@ -0,0 +1,546 @@
+// SPDX-License-Identifier: MIT
+
+// Support for --extract, which turns an existing workspace into a template.
+// Identical objects and arrays which appear more than once are hoisted into "gen.globals" and
+// referenced with "@{key}". Then, runs of identical members shared by several objects are
+// hoisted too, and expanded back with "@@{key}".
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+
+use serde_json::{Map, Value};
+
+use crate::config::Config;
+use crate::diagnostics::Diagnostics;
+use crate::jsonc;
+use crate::workspace::{self, child_pointer, Error, GEN_GLOBALS_KEY};
+
+/// Values with less scalars than this aren't worth a global
+const MIN_WEIGHT: usize = 2;
+
+/// A value which might be worth hoisting into a global
+struct Candidate {
+    value: Value,
+
+    /// Where the global's name comes from, usually the key of the first occurrence
+    hint: String,
+
+    weight: usize,
+    count: usize,
+}
+
+/// Returns a template which generates the workspace in contents.
+/// Fails if the workspace uses template syntax itself, as the template wouldn't generate it back.
+pub fn extract_template(contents: &str, current_os: &str) -> Result<Value, Error> {
+    let workspace: Value = serde_json::from_str(&jsonc::strip(contents)).map_err(Error::Json)?;
+    if !workspace.is_object() {
+        return Err(Error::ExpectedRootObject);
+    }
+
+    let mut template = workspace.clone();
+    let mut globals = Map::new();
+
+    // Whole subtrees first, largest first, so their insides don't get hoisted separately
+    let mut candidates = Vec::new();
+    collect_subtrees(&template, "workspace", &mut candidates, &mut HashMap::new());
+    for candidate in sorted(candidates) {
+        let occurrences = count_subtrees(&template, &candidate.value)
+            + globals
+                .values()
+                .map(|g| count_subtrees(g, &candidate.value))
+                .sum::<usize>();
+        if occurrences < 2 {
+            continue;
+        }
+
+        let name = unique_name(&candidate.hint, &globals, contents);
+        let reference = Value::String(format!("@{{{}}}", name));
+        replace_subtrees(&mut template, &candidate.value, &reference);
+        for global in globals.values_mut() {
+            replace_subtrees(global, &candidate.value, &reference);
+        }
+        globals.insert(name, candidate.value);
+    }
+
+    // Then members which several objects have in common
+    let mut member_counts = HashMap::new();
+    count_members(&template, &mut member_counts);
+    for global in globals.values() {
+        count_members(global, &mut member_counts);
+    }
+    let shared: HashSet<u64> = member_counts
+        .into_iter()
+        .filter(|(_, count)| *count > 1)
+        .map(|(hash, _)| hash)
+        .collect();
+
+    let mut candidates = Vec::new();
+    let mut seen = HashMap::new();
+    collect_runs(&template, "workspace", &shared, &mut candidates, &mut seen);
+    for global in globals.values() {
+        collect_runs(global, "workspace", &shared, &mut candidates, &mut seen);
+    }
+    for candidate in sorted(candidates) {
+        let run = candidate.value.as_object().unwrap();
+        let occurrences = count_runs(&template, run)
+            + globals.values().map(|g| count_runs(g, run)).sum::<usize>();
+        if occurrences < 2 {
+            continue;
+        }
+
+        let name = unique_name(&format!("{}_common", candidate.hint), &globals, contents);
+        let key = format!("@@{{{}}}", name);
+        replace_runs(&mut template, run, &key);
+        for global in globals.values_mut() {
+            replace_runs(global, run, &key);
+        }
+        globals.insert(name, candidate.value);
+    }
+
+    if !globals.is_empty() {
+        let mut with_globals = Map::new();
+        with_globals.insert(GEN_GLOBALS_KEY.to_string(), Value::Object(globals));
+        with_globals.extend(std::mem::take(template.as_object_mut().unwrap()));
+        template = Value::Object(with_globals);
+    }
+
+    // Make sure the template generates the original workspace
+    let generated = workspace::generate_from_template(
+        &template.to_string(),
+        None,
+        &Config::default(),
+        current_os,
+        &mut Diagnostics::default(),
+    )?;
+    if let Some(json_pointer) = first_difference(&workspace, &generated.workspace, "") {
+        return Err(Error::ExtractMismatch { json_pointer });
+    }
+
+    Ok(template)
+}
+
+/// How many scalars and object members value has
+fn weight(value: &Value) -> usize {
+    match value {
+        Value::Object(obj) => obj.values().map(|v| 1 + weight(v)).sum(),
+        Value::Array(array) => array.iter().map(weight).sum(),
+        _ => 1,
+    }
+}
+
+/// Keeps the candidates which appear more than once, heaviest first
+fn sorted(candidates: Vec<Candidate>) -> Vec<Candidate> {
+    let mut candidates: Vec<Candidate> = candidates.into_iter().filter(|c| c.count > 1).collect();
+    candidates.sort_by_key(|c| std::cmp::Reverse(c.weight));
+    candidates
+}
+
+/// Hashes value, for finding the candidates seen already without keeping their serialization
+fn hash_value(value: &Value) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.to_string().hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Hashes an object member, its key and its value
+fn hash_member(key: &str, value: &Value) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hash_value(value).hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Adds a candidate, or counts one more occurrence if one with the same hash is known already.
+/// value is only called for new candidates.
+fn add_candidate(
+    hash: u64,
+    weight: usize,
+    value: impl FnOnce() -> Value,
+    hint: &str,
+    candidates: &mut Vec<Candidate>,
+    seen: &mut HashMap<u64, usize>,
+) {
+    if weight < MIN_WEIGHT {
+        return;
+    }
+
+    match seen.get(&hash) {
+        Some(&index) => candidates[index].count += 1,
+        None => {
+            seen.insert(hash, candidates.len());
+            candidates.push(Candidate {
+                value: value(),
+                hint: hint.to_string(),
+                weight,
+                count: 1,
+            });
+        }
+    }
+}
+
+/// Adds value as a candidate
+fn add_subtree(
+    value: &Value,
+    hint: &str,
+    candidates: &mut Vec<Candidate>,
+    seen: &mut HashMap<u64, usize>,
+) {
+    add_candidate(
+        hash_value(value),
+        weight(value),
+        || value.clone(),
+        hint,
+        candidates,
+        seen,
+    );
+}
+
+fn collect_subtrees(
+    value: &Value,
+    hint: &str,
+    candidates: &mut Vec<Candidate>,
+    seen: &mut HashMap<u64, usize>,
+) {
+    // Parents go first, so they win over their children when equally heavy
+    match value {
+        Value::Object(obj) => {
+            add_subtree(value, hint, candidates, seen);
+            for (k, v) in obj {
+                collect_subtrees(v, k, candidates, seen);
+            }
+        }
+        Value::Array(array) => {
+            add_subtree(value, hint, candidates, seen);
+            for v in array {
+                collect_subtrees(v, &format!("{}_item", hint), candidates, seen);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Counts the occurrences of target inside value
+fn count_subtrees(value: &Value, target: &Value) -> usize {
+    if identical(value, target) {
+        return 1;
+    }
+
+    match value {
+        Value::Object(obj) => obj.values().map(|v| count_subtrees(v, target)).sum(),
+        Value::Array(array) => array.iter().map(|v| count_subtrees(v, target)).sum(),
+        _ => 0,
+    }
+}
+
+fn replace_subtrees(value: &mut Value, target: &Value, replacement: &Value) {
+    if identical(value, target) {
+        *value = replacement.clone();
+        return;
+    }
+
+    match value {
+        Value::Object(obj) => {
+            for v in obj.values_mut() {
+                replace_subtrees(v, target, replacement);
+            }
+        }
+        Value::Array(array) => {
+            for v in array {
+                replace_subtrees(v, target, replacement);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Counts the objects each member, by hash_member, appears in
+fn count_members(value: &Value, counts: &mut HashMap<u64, usize>) {
+    match value {
+        Value::Object(obj) => {
+            for (k, v) in obj {
+                *counts.entry(hash_member(k, v)).or_default() += 1;
+                count_members(v, counts);
+            }
+        }
+        Value::Array(array) => {
+            for v in array {
+                count_members(v, counts);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Collects every run of two or more consecutive members of every object, which only has
+/// shared members, the ones other objects have too, as only those runs can appear twice
+fn collect_runs(
+    value: &Value,
+    hint: &str,
+    shared: &HashSet<u64>,
+    candidates: &mut Vec<Candidate>,
+    seen: &mut HashMap<u64, usize>,
+) {
+    match value {
+        Value::Object(obj) => {
+            let members: Vec<(&String, &Value, u64, usize)> = obj
+                .iter()
+                .map(|(k, v)| (k, v, hash_member(k, v), 1 + weight(v)))
+                .collect();
+
+            for start in 0..members.len() {
+                // The run's hash and weight grow with each member
+                let mut hasher = DefaultHasher::new();
+                let mut run_weight = 0;
+                for (end, (_, _, hash, member_weight)) in members.iter().enumerate().skip(start) {
+                    if !shared.contains(hash) {
+                        break;
+                    }
+                    hash.hash(&mut hasher);
+                    run_weight += member_weight;
+                    if end == start {
+                        continue;
+                    }
+
+                    let run = || {
+                        let run: Map<String, Value> = members[start..=end]
+                            .iter()
+                            .map(|(k, v, _, _)| ((*k).clone(), (*v).clone()))
+                            .collect();
+                        Value::Object(run)
+                    };
+                    add_candidate(hasher.finish(), run_weight, run, hint, candidates, seen);
+                }
+            }
+
+            for (k, v) in obj {
+                collect_runs(v, k, shared, candidates, seen);
+            }
+        }
+        Value::Array(array) => {
+            for v in array {
+                collect_runs(v, &format!("{}_item", hint), shared, candidates, seen);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Returns where run starts inside obj, if obj has all of run's members, consecutively
+fn find_run(obj: &Map<String, Value>, run: &Map<String, Value>) -> Option<usize> {
+    let (first_key, _) = run.iter().next()?;
+    let start = obj.keys().position(|k| k == first_key)?;
+    let matches = obj.len() - start >= run.len()
+        && obj
+            .iter()
+            .skip(start)
+            .zip(run)
+            .all(|((k, v), (rk, rv))| k == rk && identical(v, rv));
+
+    matches.then_some(start)
+}
+
+/// Counts the objects inside value which contain run
+fn count_runs(value: &Value, run: &Map<String, Value>) -> usize {
+    match value {
+        Value::Object(obj) => {
+            usize::from(find_run(obj, run).is_some())
+                + obj.values().map(|v| count_runs(v, run)).sum::<usize>()
+        }
+        Value::Array(array) => array.iter().map(|v| count_runs(v, run)).sum(),
+        _ => 0,
+    }
+}
+
+/// Replaces run with a single key, in every object which contains it
+fn replace_runs(value: &mut Value, run: &Map<String, Value>, key: &str) {
+    match value {
+        Value::Object(obj) => {
+            if let Some(start) = find_run(obj, run) {
+                let mut new_obj = Map::new();
+                for (i, (k, v)) in std::mem::take(obj).into_iter().enumerate() {
+                    if i == start {
+                        new_obj.insert(key.to_string(), Value::String(String::new()));
+                    }
+                    if i < start || i >= start + run.len() {
+                        new_obj.insert(k, v);
+                    }
+                }
+                *obj = new_obj;
+            }
+
+            for v in obj.values_mut() {
+                replace_runs(v, run, key);
+            }
+        }
+        Value::Array(array) => {
+            for v in array {
+                replace_runs(v, run, key);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Turns hint into a global's name which isn't taken, nor referenced by the original workspace
+fn unique_name(hint: &str, globals: &Map<String, Value>, contents: &str) -> String {
+    let base: String = hint
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    let mut name = base.clone();
+    let mut i = 2;
+    while globals.contains_key(&name) || contents.contains(&format!("@{{{}", name)) {
+        name = format!("{}{}", base, i);
+        i += 1;
+    }
+
+    name
+}
+
+/// Like ==, but objects with the same members in different order are different
+fn identical(a: &Value, b: &Value) -> bool {
+    // == is cheaper, so it goes first
+    a == b && first_difference(a, b, "").is_none()
+}
+
+/// Returns the pointer of the first place where a and b differ.
+/// Unlike ==, objects with the same members in different order are different.
+fn first_difference(a: &Value, b: &Value, pointer: &str) -> Option<String> {
+    match (a, b) {
+        (Value::Object(a), Value::Object(b)) => {
+            for ((ka, va), (kb, vb)) in a.iter().zip(b) {
+                let child = child_pointer(pointer, ka);
+                if ka != kb {
+                    return Some(child);
+                }
+                if let Some(difference) = first_difference(va, vb, &child) {
+                    return Some(difference);
+                }
+            }
+            (a.len() != b.len()).then(|| pointer.to_string())
+        }
+        (Value::Array(a), Value::Array(b)) => {
+            for (i, (va, vb)) in a.iter().zip(b).enumerate() {
+                let child = child_pointer(pointer, &i.to_string());
+                if let Some(difference) = first_difference(va, vb, &child) {
+                    return Some(difference);
+                }
+            }
+            (a.len() != b.len()).then(|| pointer.to_string())
+        }
+        _ => (a != b).then(|| pointer.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WORKSPACE: &str = r#"{
+    "folders": [{ "path": "." }],
+    "launch": {
+        "configurations": [
+            {
+                "name": "app",
+                "type": "cppdbg",
+                "request": "launch",
+                "program": "${workspaceFolder}/build/app",
+                "setupCommands": [
+                    { "text": "-enable-pretty-printing", "ignoreFailures": true }
+                ],
+                "sourceFileMap": { "/build": "${workspaceFolder}" }
+            },
+            {
+                "name": "tests",
+                "type": "cppdbg",
+                "request": "launch",
+                "program": "${workspaceFolder}/build/tests",
+                "setupCommands": [
+                    { "text": "-enable-pretty-printing", "ignoreFailures": true }
+                ],
+                "sourceFileMap": { "/build": "${workspaceFolder}" }
+            }
+        ]
+    }
+}"#;
+
+    #[test]
+    fn test_extract_template() {
+        let template = extract_template(WORKSPACE, "linux").unwrap();
+
+        let expected: Value = serde_json::from_str(
+            r#"{
+            "gen.globals": {
+                "setupCommands": [
+                    { "text": "-enable-pretty-printing", "ignoreFailures": true }
+                ],
+                "sourceFileMap": { "/build": "${workspaceFolder}" },
+                "configurations_item_common": {
+                    "type": "cppdbg",
+                    "request": "launch"
+                },
+                "configurations_item_common2": {
+                    "setupCommands": "@{setupCommands}",
+                    "sourceFileMap": "@{sourceFileMap}"
+                }
+            },
+            "folders": [{ "path": "." }],
+            "launch": {
+                "configurations": [
+                    {
+                        "name": "app",
+                        "@@{configurations_item_common}": "",
+                        "program": "${workspaceFolder}/build/app",
+                        "@@{configurations_item_common2}": ""
+                    },
+                    {
+                        "name": "tests",
+                        "@@{configurations_item_common}": "",
+                        "program": "${workspaceFolder}/build/tests",
+                        "@@{configurations_item_common2}": ""
+                    }
+                ]
+            }
+        }"#,
+        )
+        .unwrap();
+        assert_eq!(first_difference(&template, &expected, ""), None);
+
+        // Round-trips
+        let generated = workspace::generate_from_string(&template.to_string(), "linux").unwrap();
+        let original: Value = serde_json::from_str(WORKSPACE).unwrap();
+        assert_eq!(first_difference(&generated, &original, ""), None);
+
+        // Nothing repeated, nothing to hoist
+        let template = extract_template(r#"{ "a": [1, 2], "b": { "c": 3 } }"#, "linux").unwrap();
+        assert_eq!(template.to_string(), r#"{"a":[1,2],"b":{"c":3}}"#);
+    }
+
+    #[test]
+    fn test_extract_template_errors() {
+        // Would be expanded when generating
+        let workspace = r#"{ "d": { "gen.os": "linux", "x": 1 } }"#;
+        assert!(matches!(
+            extract_template(workspace, "linux"),
+            Err(Error::ExtractMismatch { json_pointer }) if json_pointer == "/d/gen.os"
+        ));
+
+        // Names don't clash with what the workspace references
+        let workspace = r#"{ "a": [1, 2], "b": [1, 2], "c": "@{a}" }"#;
+        let template = extract_template(workspace, "linux").unwrap();
+        assert!(template[GEN_GLOBALS_KEY].get("a2").is_some());
+
+        assert!(matches!(
+            extract_template("[]", "linux"),
+            Err(Error::ExpectedRootObject)
+        ));
+    }
+}
@@ -0,0 +1,163 @@
+// SPDX-License-Identifier: MIT
+
+// Support for "gen.include", which pulls globals and fragments from other template files.
+// Paths are relative to the including template. Includes can include other files.
+
+use std::path::{Path, PathBuf};
+
+use serde_json::{Map, Value};
+
+use crate::jsonc;
+use crate::workspace::{Error, GEN_GLOBALS_KEY};
+
+const GEN_INCLUDE_KEY: &str = "gen.include";
+
+/// Parses a template and merges the files it includes into it
+/// filename is the template's own path, if any, so we can detect it being included again
+/// If keep_comments is true, comments are attached to their keys, see jsonc.rs
+pub fn parse_template(
+    template_contents: &str,
+    base_dir: &Path,
+    filename: Option<&Path>,
+    keep_comments: bool,
+) -> Result<Value, Error> {
+    let mut chain = Vec::new();
+    if let Some(filename) = filename {
+        chain.push(filename.canonicalize().unwrap_or(filename.to_path_buf()));
+    }
+
+    parse(template_contents, base_dir, &mut chain, keep_comments)
+}
+
+/// Returns the files included by the template at path, recursively, by priority: later includes
+/// first, and each file before the ones it includes, like when merging them.
+/// Files which can't be read or parsed are skipped, as generating will report them.
+pub fn included_files(path: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    collect_included_files(path, &mut files);
+    files
+}
+
+fn collect_included_files(path: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(contents) = std::fs::read_to_string(path) else {
+        return;
+    };
+    let Ok(json) = serde_json::from_str::<Value>(&jsonc::strip(&contents)) else {
+        return;
+    };
+    let Some(Ok(filenames)) = json.get(GEN_INCLUDE_KEY).map(include_filenames) else {
+        return;
+    };
+
+    let base_dir = path.parent().unwrap_or(Path::new("."));
+    for filename in filenames.iter().rev() {
+        let included = base_dir.join(filename);
+        if !files.contains(&included) {
+            files.push(included.clone());
+            collect_included_files(&included, files);
+        }
+    }
+}
+
+/// chain holds the files currently being included, so we can detect cycles
+fn parse(
+    contents: &str,
+    base_dir: &Path,
+    chain: &mut Vec<PathBuf>,
+    keep_comments: bool,
+) -> Result<Value, Error> {
+    let mut json: Value = serde_json::from_str(&jsonc::strip(contents)).map_err(Error::Json)?;
+    if keep_comments {
+        jsonc::attach_comments(&mut json, jsonc::key_comments(contents));
+    }
+
+    let Some(obj) = json.as_object_mut() else {
+        return Err(Error::ExpectedRootObject);
+    };
+
+    let Some(includes) = obj.shift_remove(GEN_INCLUDE_KEY) else {
+        return Ok(json);
+    };
+
+    // Later includes have priority over earlier ones, and the including file has priority over all
+    for include in include_filenames(&includes)?.iter().rev() {
+        let path = base_dir.join(include);
+        let included = include_file(&path, chain, keep_comments).map_err(|e| match e {
+            Error::IncludeCycle(_) | Error::Include { .. } => e,
+            _ => Error::Include {
+                filename: path.display().to_string(),
+                error: Box::new(e),
+            },
+        })?;
+
+        fill_missing(obj, included, true);
+    }
+
+    Ok(json)
+}
+
+fn include_file(
+    path: &Path,
+    chain: &mut Vec<PathBuf>,
+    keep_comments: bool,
+) -> Result<Map<String, Value>, Error> {
+    let canonical = path.canonicalize().map_err(Error::Io)?;
+
+    if chain.contains(&canonical) {
+        let mut cycle: Vec<String> = chain.iter().map(|p| p.display().to_string()).collect();
+        cycle.push(canonical.display().to_string());
+        return Err(Error::IncludeCycle(cycle));
+    }
+
+    let contents = std::fs::read_to_string(&canonical).map_err(Error::Io)?;
+    let base_dir = canonical.parent().unwrap().to_path_buf();
+
+    chain.push(canonical);
+    let json = parse(&contents, &base_dir, chain, keep_comments)?;
+    chain.pop();
+
+    match json {
+        Value::Object(obj) => Ok(obj),
+        _ => Err(Error::ExpectedRootObject),
+    }
+}
+
+/// "gen.include" accepts a single filename or a list of them
+fn include_filenames(value: &Value) -> Result<Vec<String>, Error> {
+    let invalid = || Error::InvalidDirective {
+        key: GEN_INCLUDE_KEY.to_string(),
+        message: "Expected a filename or a list of filenames".to_string(),
+        json_pointer: format!("/{}", GEN_INCLUDE_KEY),
+    };
+
+    match value {
+        Value::String(filename) => Ok(vec![filename.clone()]),
+        Value::Array(filenames) => filenames
+            .iter()
+            .map(|f| f.as_str().map(str::to_string).ok_or_else(invalid))
+            .collect(),
+        _ => Err(invalid()),
+    }
+}
+
+/// Inserts the keys of source which target doesn't have yet. Objects are merged recursively,
+/// except for globals, which are only merged at the first level, so a global is never mixed with another.
+fn fill_missing(target: &mut Map<String, Value>, source: Map<String, Value>, is_root: bool) {
+    for (key, value) in source {
+        match (target.get_mut(&key), value) {
+            (None, value) => {
+                target.insert(key, value);
+            }
+            (Some(Value::Object(target_obj)), Value::Object(source_obj)) => {
+                if is_root && key == GEN_GLOBALS_KEY {
+                    for (global_key, global) in source_obj {
+                        target_obj.entry(global_key).or_insert(global);
+                    }
+                } else {
+                    fill_missing(target_obj, source_obj, false);
+                }
+            }
+            _ => (),
+        }
+    }
+}
@@ -0,0 +1,406 @@
+// SPDX-License-Identifier: MIT
+
+// Support for JSONC, the JSON flavour VS Code uses for its settings and workspaces.
+// Comments and trailing commas are replaced by spaces before parsing, so line and column
+// numbers stay valid for error messages.
+// With "preserve_comments", the comments before or next to an object member are kept in the
+// JSON value under a "gen.comment:<key>" sibling key, so they follow the member when it's
+// expanded, and are written back as "//" comments.
+
+use serde_json::{Map, Value};
+
+const COMMENT_KEY_PREFIX: &str = "gen.comment:";
+
+/// Returns contents with comments and trailing commas replaced by spaces.
+/// Newlines are kept and every byte stays in place.
+pub fn strip(contents: &str) -> String {
+    let mut bytes = contents.as_bytes().to_vec();
+
+    // The last comma, if only whitespace and comments came after it
+    let mut pending_comma = None;
+
+    let mut i = 0;
+    while i < bytes.len() {
+        match (bytes[i], bytes.get(i + 1)) {
+            (b'"', _) => {
+                pending_comma = None;
+                i = string_end(&bytes, i);
+            }
+            (b'/', Some(b'/' | b'*')) => {
+                let end = comment_end(&bytes, i);
+                for b in &mut bytes[i..end] {
+                    if *b != b'\n' && *b != b'\r' {
+                        *b = b' ';
+                    }
+                }
+                i = end;
+            }
+            (b',', _) => {
+                pending_comma = Some(i);
+                i += 1;
+            }
+            (b'}' | b']', _) => {
+                if let Some(comma) = pending_comma.take() {
+                    bytes[comma] = b' ';
+                }
+                i += 1;
+            }
+            (c, _) => {
+                if !c.is_ascii_whitespace() {
+                    pending_comma = None;
+                }
+                i += 1;
+            }
+        }
+    }
+
+    // Only ASCII bytes were replaced, by ASCII spaces
+    String::from_utf8(bytes).unwrap()
+}
+
+/// Returns the position after the string which starts at start
+fn string_end(bytes: &[u8], start: usize) -> usize {
+    let mut i = start + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'"' => return i + 1,
+            _ => i += 1,
+        }
+    }
+
+    bytes.len()
+}
+
+/// Returns the position after the comment which starts at start. Line comments end before the newline.
+fn comment_end(bytes: &[u8], start: usize) -> usize {
+    if bytes[start + 1] == b'/' {
+        return bytes[start..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(bytes.len(), |p| start + p);
+    }
+
+    bytes[start + 2..]
+        .windows(2)
+        .position(|w| w == b"*/")
+        .map_or(bytes.len(), |p| start + 2 + p + 2)
+}
+
+enum Frame {
+    /// key is the last key read
+    Object {
+        key: Option<String>,
+    },
+    Array {
+        index: usize,
+    },
+}
+
+/// Returns the comments of each object member, by the member's JSON pointer.
+/// A member's comments are the ones on the lines before it and the ones after it on the same line.
+pub fn key_comments(contents: &str) -> Vec<(String, Vec<String>)> {
+    let bytes = contents.as_bytes();
+    let mut result: Vec<(String, Vec<String>)> = Vec::new();
+    let mut frames: Vec<Frame> = Vec::new();
+    let mut pending = Vec::new();
+    let mut expecting_key = false;
+    let mut line = 0;
+    let mut last_token_line = None;
+
+    // The pointer of the innermost member we're in, if the innermost container is an object
+    let member_pointer = |frames: &[Frame]| {
+        let mut pointer = String::new();
+        let mut is_member = false;
+        for frame in frames {
+            let token = match frame {
+                Frame::Object { key: Some(key) } => key.replace('~', "~0").replace('/', "~1"),
+                Frame::Object { key: None } => continue,
+                Frame::Array { index } => index.to_string(),
+            };
+            is_member = matches!(frame, Frame::Object { .. });
+            pointer.push('/');
+            pointer.push_str(&token);
+        }
+        is_member.then_some(pointer)
+    };
+
+    let mut add = |pointer: String, comments: Vec<String>| match result
+        .iter_mut()
+        .find(|(p, _)| *p == pointer)
+    {
+        Some((_, existing)) => existing.extend(comments),
+        None => result.push((pointer, comments)),
+    };
+
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        match (c, bytes.get(i + 1)) {
+            (b'\n', _) => {
+                line += 1;
+                i += 1;
+                continue;
+            }
+            (c, _) if c.is_ascii_whitespace() => {
+                i += 1;
+                continue;
+            }
+            (b'/', Some(b'/' | b'*')) => {
+                let end = comment_end(bytes, i);
+                let comment = contents[i..end].to_string();
+                if last_token_line == Some(line) {
+                    if let Some(pointer) = member_pointer(&frames) {
+                        add(pointer, vec![comment]);
+                    }
+                } else {
+                    pending.push(comment);
+                }
+                line += contents[i..end].matches('\n').count();
+                i = end;
+                continue;
+            }
+            (b'"', _) => {
+                let end = string_end(bytes, i);
+                if let (true, Some(Frame::Object { key })) = (expecting_key, frames.last_mut()) {
+                    *key = serde_json::from_str(&contents[i..end]).ok();
+                    expecting_key = false;
+                    if !pending.is_empty() {
+                        if let Some(pointer) = member_pointer(&frames) {
+                            add(pointer, std::mem::take(&mut pending));
+                        }
+                    }
+                }
+                pending.clear();
+                i = end;
+            }
+            (b'{', _) => {
+                frames.push(Frame::Object { key: None });
+                expecting_key = true;
+                i += 1;
+            }
+            (b'[', _) => {
+                frames.push(Frame::Array { index: 0 });
+                i += 1;
+            }
+            (b'}' | b']', _) => {
+                frames.pop();
+                pending.clear();
+                i += 1;
+            }
+            (b',', _) => {
+                match frames.last_mut() {
+                    Some(Frame::Object { .. }) => expecting_key = true,
+                    Some(Frame::Array { index }) => *index += 1,
+                    None => (),
+                }
+                i += 1;
+            }
+            _ => {
+                // ':' and scalars
+                if c != b':' {
+                    pending.clear();
+                }
+                i += 1;
+            }
+        }
+
+        last_token_line = Some(line);
+    }
+
+    result
+}
+
+fn comment_key(key: &str) -> String {
+    format!("{}{}", COMMENT_KEY_PREFIX, key)
+}
+
+/// Whether key holds the comments of another key
+pub fn is_comment_key(key: &str) -> bool {
+    key.starts_with(COMMENT_KEY_PREFIX)
+}
+
+/// Stores comments, as returned by key_comments(), next to the keys they belong to
+pub fn attach_comments(json: &mut Value, comments: Vec<(String, Vec<String>)>) {
+    for (pointer, comments) in comments {
+        let Some((parent, key)) = pointer.rsplit_once('/') else {
+            continue;
+        };
+        let key = key.replace("~1", "/").replace("~0", "~");
+
+        if let Some(Value::Object(obj)) = json.pointer_mut(parent) {
+            if obj.contains_key(&key) {
+                let comments = comments.into_iter().map(Value::String).collect();
+                obj.insert(comment_key(&key), Value::Array(comments));
+            }
+        }
+    }
+}
+
+/// Removes the comments attached by attach_comments()
+pub fn remove_comments(json: &mut Value) {
+    match json {
+        Value::Object(obj) => {
+            obj.retain(|k, _| !is_comment_key(k));
+            for v in obj.values_mut() {
+                remove_comments(v);
+            }
+        }
+        Value::Array(array) => {
+            for v in array {
+                remove_comments(v);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Serializes json like to_json_string() does, but writes attached comments before their keys
+pub fn to_string(json: &Value, indent: u32) -> String {
+    let mut result = String::new();
+    write_value(&mut result, json, &" ".repeat(indent as usize), 0);
+    result
+}
+
+fn write_value(result: &mut String, value: &Value, indent: &str, level: usize) {
+    let inner_indent = indent.repeat(level + 1);
+    match value {
+        Value::Object(obj) => {
+            let members: Vec<(&String, &Value)> =
+                obj.iter().filter(|(k, _)| !is_comment_key(k)).collect();
+            if members.is_empty() {
+                result.push_str("{}");
+                return;
+            }
+
+            result.push('{');
+            for (i, (k, v)) in members.into_iter().enumerate() {
+                result.push_str(if i == 0 { "\n" } else { ",\n" });
+                write_comments(result, obj, k, &inner_indent);
+                result.push_str(&inner_indent);
+                result.push_str(&Value::String(k.clone()).to_string());
+                result.push_str(": ");
+                write_value(result, v, indent, level + 1);
+            }
+            result.push('\n');
+            result.push_str(&indent.repeat(level));
+            result.push('}');
+        }
+        Value::Array(array) => {
+            if array.is_empty() {
+                result.push_str("[]");
+                return;
+            }
+
+            result.push('[');
+            for (i, v) in array.iter().enumerate() {
+                result.push_str(if i == 0 { "\n" } else { ",\n" });
+                result.push_str(&inner_indent);
+                write_value(result, v, indent, level + 1);
+            }
+            result.push('\n');
+            result.push_str(&indent.repeat(level));
+            result.push(']');
+        }
+        scalar => result.push_str(&scalar.to_string()),
+    }
+}
+
+fn write_comments(result: &mut String, obj: &Map<String, Value>, key: &str, indent: &str) {
+    let Some(Value::Array(comments)) = obj.get(&comment_key(key)) else {
+        return;
+    };
+
+    for line in comments
+        .iter()
+        .filter_map(Value::as_str)
+        .flat_map(str::lines)
+    {
+        result.push_str(indent);
+        result.push_str(line.trim());
+        result.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = r#"{
+    // The folders
+    "folders": [{ "path": "." },],
+    "settings": { /* "a": 1, */
+        "url": "http://example.com", // not a comment inside strings
+        "b": [1, 2, ], /* trailing */
+    },
+}"#;
+
+    #[test]
+    fn test_strip() {
+        let stripped = strip(SOURCE);
+        assert_eq!(stripped.len(), SOURCE.len());
+        assert_eq!(stripped.lines().count(), SOURCE.lines().count());
+
+        let json: Value = serde_json::from_str(&stripped).unwrap();
+        let expected: Value = serde_json::from_str(
+            r#"{
+            "folders": [{ "path": "." }],
+            "settings": { "url": "http://example.com", "b": [1, 2] }
+        }"#,
+        )
+        .unwrap();
+        assert_eq!(json, expected);
+
+        // Unterminated comments are left for the parser to complain about
+        assert!(serde_json::from_str::<Value>(&strip(r#"{ "a": 1 /* "#)).is_err());
+        assert_eq!(strip(r#"[",]", "//"]"#), r#"[",]", "//"]"#);
+    }
+
+    #[test]
+    fn test_key_comments() {
+        let comments = key_comments(SOURCE);
+        assert_eq!(
+            comments,
+            vec![
+                ("/folders".to_string(), vec!["// The folders".to_string()]),
+                ("/settings".to_string(), vec!["/* \"a\": 1, */".to_string()]),
+                (
+                    "/settings/url".to_string(),
+                    vec!["// not a comment inside strings".to_string()]
+                ),
+                (
+                    "/settings/b".to_string(),
+                    vec!["/* trailing */".to_string()]
+                ),
+            ]
+        );
+
+        let mut json: Value = serde_json::from_str(&strip(SOURCE)).unwrap();
+        attach_comments(&mut json, comments);
+        let expected = r#"{
+  // The folders
+  "folders": [
+    {
+      "path": "."
+    }
+  ],
+  /* "a": 1, */
+  "settings": {
+    // not a comment inside strings
+    "url": "http://example.com",
+    /* trailing */
+    "b": [
+      1,
+      2
+    ]
+  }
+}"#;
+        assert_eq!(to_string(&json, 2), expected);
+
+        remove_comments(&mut json);
+        assert_eq!(
+            to_string(&json, 2),
+            serde_json::to_string_pretty(&json).unwrap()
+        );
+    }
+}
@@ -1,11 +1,21 @@
 // SPDX-License-Identifier: MIT
 
 use clap::Parser;
-use std::{env, process};
+use std::{env, path::PathBuf, process};
 
+mod cargo;
+mod cmake;
+mod condition;
 mod config;
+mod diagnostics;
+mod diff;
+mod extract;
+mod include;
+mod jsonc;
+mod patch;
 mod project;
 mod qt;
+mod watch;
 mod workspace;
 
 #[cfg(test)]
@@ -14,13 +24,46 @@ mod tests;
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    // Add -o option to specify output file
+    // Add -o option to specify output file. "-" prints the workspace to stdout instead.
     #[arg(short, long)]
     output_name: Option<String>,
 
     #[arg(short, long)]
     template_filename: Option<String>,
 
+    /// Fail on unresolved references instead of leaving them in the output
+    #[arg(long)]
+    strict: bool,
+
+    /// Defines a variable, which overrides the global with the same name. Can be repeated.
+    #[arg(short = 'D', long = "define", value_name = "NAME=VALUE", value_parser = parse_define)]
+    defines: Vec<(String, String)>,
+
+    /// Use the settings of a profile from the config file
+    #[arg(long)]
+    profile: Option<String>,
+
+    /// Generate every profile from the config file
+    #[arg(long, conflicts_with_all = ["profile", "output_name", "extract"])]
+    all_profiles: bool,
+
+    /// Keep running and regenerate whenever the template or the config file change
+    #[arg(short, long)]
+    watch: bool,
+
+    /// Don't write anything, but fail if the generated files aren't up to date
+    #[arg(long, conflicts_with = "watch")]
+    check: bool,
+
+    /// Don't write anything, only print which files would be written
+    #[arg(long, conflicts_with_all = ["watch", "check"])]
+    dry_run: bool,
+
+    /// Create a template from an existing workspace, hoisting repeated parts into globals.
+    /// The template is written to -t, or next to the workspace.
+    #[arg(long, value_name = "WORKSPACE", conflicts_with_all = ["watch", "check"])]
+    extract: Option<String>,
+
     #[command(flatten)]
     projects: CreateProjArgs,
 }
@@ -100,79 +143,350 @@ fn handle_projects_usecase() {
     }
 }
 
-fn main() {
-    // Handle -c, --create-project and -a, --create-template-project. Exits if handled.
-    handle_projects_usecase();
+fn parse_define(define: &str) -> Result<(String, String), String> {
+    match define.split_once('=') {
+        Some((name, value)) if !name.trim().is_empty() => {
+            Ok((name.trim().to_string(), value.to_string()))
+        }
+        _ => Err("Expected NAME=VALUE".to_string()),
+    }
+}
 
-    // Handle the main use case:
+/// Loads the config file, once per profile we're asked to generate
+fn load_configs(args: &Args) -> Result<Vec<config::Config>, String> {
+    let config = config::Config::from_default_file()
+        .map_err(|e| format!("Config file exists but can't be parsed: {}", e))?;
 
-    let args = Args::parse();
+    let mut configs = if args.all_profiles {
+        if config.profile_names().is_empty() {
+            return Err("--all-profiles was passed, but there are no profiles".to_string());
+        }
+        config
+            .profile_names()
+            .into_iter()
+            .map(|name| config.with_profile(name))
+            .collect::<Result<Vec<_>, _>>()?
+    } else if let Some(profile) = &args.profile {
+        vec![config.with_profile(profile)?]
+    } else {
+        vec![config]
+    };
 
-    let config =
-        config::Config::from_default_file().expect("Config file exists but can't be parsed");
+    for config in &mut configs {
+        if args.strict {
+            config.set_strict(true);
+        }
 
-    if let Err(e) = config.is_valid() {
-        println!("Config: {}", e);
-        process::exit(-1);
+        // The command line has priority over profiles
+        for (name, value) in &args.defines {
+            config.set_variable(name.clone(), serde_json::Value::String(value.clone()));
+        }
+
+        config.is_valid()?;
+        has_targets(args, config)?;
     }
 
-    let template_filename = args
-        .template_filename
-        .clone()
-        .expect("You're expected to pass: -t <template_filename>");
+    Ok(configs)
+}
+
+/// Fails if the config's outputs are all filtered out, so nothing would be generated.
+/// -o replaces the config's outputs.
+fn has_targets(args: &Args, config: &config::Config) -> Result<(), String> {
+    if args.output_name.is_some() || !config.outputs().is_some_and(|outputs| outputs.is_empty()) {
+        return Ok(());
+    }
 
-    let result: Result<(), workspace::Error> = if let Some(output_filename) = &args.output_name {
+    let message = "Nothing to generate, as none of the OSes in per_os_output_filenames which have a filename is in os_targets";
+    match config.profile() {
+        Some(profile) => Err(format!("Profile {}: {}", profile, message)),
+        None => Err(message.to_string()),
+    }
+}
+
+/// Returns the OS and the output filename of every file we're asked to generate
+fn targets(args: &Args, config: &config::Config, template_filename: &str) -> Vec<(String, String)> {
+    if let Some(output_filename) = &args.output_name {
         // Case 1. User passed -o <output_filename>
-        workspace::generate_from_file(
-            template_filename,
-            output_filename.to_string(),
-            &config,
-            env::consts::OS,
-        )
+        vec![(env::consts::OS.to_string(), output_filename.clone())]
     } else if config.has_output() {
         // Case 2. There's a .vscode-workspace-gen.json config file with either 'output_filename' or 'per_os_output_filename's set
         let targets = config.outputs().expect("Config has no usable targets");
-        let mut last_result: Result<(), workspace::Error> = Ok(());
-        for (os, output_filename) in targets {
-            last_result = workspace::generate_from_file(
-                template_filename.clone(),
-                output_filename.clone(),
-                &config,
-                os,
+        targets
+            .into_iter()
+            .map(|(os, output_filename)| (os.to_string(), output_filename.clone()))
+            .collect()
+    } else {
+        // 3. Let's simply remove ".template" from the template filename
+        vec![(
+            env::consts::OS.to_string(),
+            suggest_output_filename(template_filename),
+        )]
+    }
+}
+
+/// Output filename which means stdout
+const STDOUT_FILENAME: &str = "-";
+
+/// Whether generate() writes to disk, as opposed to --dry-run and -o -
+fn writes_files(args: &Args) -> bool {
+    !args.dry_run && args.output_name.as_deref() != Some(STDOUT_FILENAME)
+}
+
+/// Rejects the combinations of arguments clap can't express
+fn validate_args(args: &Args) -> Result<(), String> {
+    if args.check && args.output_name.as_deref() == Some(STDOUT_FILENAME) {
+        return Err(
+            "--check compares the generated files with the existing ones, it can't be used with -o -"
+                .to_string(),
+        );
+    }
+
+    Ok(())
+}
+
+/// Generates every output we're asked to
+fn generate(
+    args: &Args,
+    config: &config::Config,
+    template_filename: &str,
+) -> Result<(), workspace::Error> {
+    workspace::warn_about_os_values(template_filename, config);
+
+    // The CMake presets are only written once, for the first target
+    let mut written = Vec::new();
+    for (os, output_filename) in targets(args, config, template_filename) {
+        if args.dry_run {
+            let outputs =
+                workspace::generate_outputs(template_filename, &output_filename, config, &os)?;
+            for output in outputs {
+                if !written.contains(&output.filename) {
+                    println!("{}", output.filename.display());
+                    written.push(output.filename);
+                }
+            }
+        } else if output_filename == STDOUT_FILENAME {
+            let outputs =
+                workspace::generate_outputs(template_filename, &output_filename, config, &os)?;
+
+            // Only the workspace goes to stdout, so it stays valid JSON
+            let (workspace, others) = outputs.split_first().unwrap();
+            println!("{}", workspace.contents);
+            for output in others {
+                eprintln!(
+                    "Not writing {}, as output goes to stdout",
+                    output.filename.display()
+                );
+            }
+        } else {
+            workspace::generate_from_file(
+                template_filename.to_string(),
+                output_filename,
+                config,
+                &os,
+                &mut written,
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Generates every output of every profile
+fn generate_profiles(
+    args: &Args,
+    configs: &[config::Config],
+    template_filename: &str,
+) -> Result<(), workspace::Error> {
+    for config in configs {
+        if let Some(profile) = config.profile() {
+            eprintln!("Profile {}:", profile);
+        }
+        generate(args, config, template_filename)?;
+    }
+
+    Ok(())
+}
+
+/// Compares what would be generated with the existing files, without writing anything.
+/// Prints a diff for each file which is out of date and returns whether all are up to date.
+fn check(
+    args: &Args,
+    config: &config::Config,
+    template_filename: &str,
+) -> Result<bool, workspace::Error> {
+    workspace::warn_about_os_values(template_filename, config);
+
+    let mut up_to_date = true;
+    let mut checked = Vec::new();
+    for (os, output_filename) in targets(args, config, template_filename) {
+        let outputs =
+            workspace::generate_outputs(template_filename, &output_filename, config, &os)?;
+
+        // The CMake presets are only checked once, like they are only written once
+        for output in outputs {
+            if checked.contains(&output.filename) {
+                continue;
+            }
+            checked.push(output.filename.clone());
+
+            let filename = output.filename.display().to_string();
+            let Ok(existing) = std::fs::read_to_string(&output.filename) else {
+                eprintln!("{} doesn't exist", filename);
+                up_to_date = false;
+                continue;
+            };
+
+            let diff = diff::unified_diff(
+                &existing,
+                &output.contents,
+                &format!("a/{}", filename),
+                &format!("b/{}", filename),
             );
 
-            if last_result.is_err() {
-                break;
+            if !diff.is_empty() {
+                eprintln!("{} is out of date", filename);
+                print!("{}", diff);
+                up_to_date = false;
             }
         }
+    }
 
-        last_result
+    Ok(up_to_date)
+}
+
+/// Writes a template which generates the workspace in workspace_filename
+fn extract(
+    args: &Args,
+    config: &config::Config,
+    workspace_filename: &str,
+) -> Result<(), workspace::Error> {
+    let contents = std::fs::read_to_string(workspace_filename).map_err(workspace::Error::Io)?;
+    let template = extract::extract_template(&contents, env::consts::OS)?;
+    let template_contents = workspace::to_json_string(&template, config.json_indent())?;
+
+    let template_filename = args
+        .template_filename
+        .clone()
+        .unwrap_or(format!("{}.template", workspace_filename));
+
+    if args.dry_run {
+        println!("{}", template_filename);
+    } else if template_filename == STDOUT_FILENAME {
+        println!("{}", template_contents);
     } else {
-        // 3. Let's simply remove ".template" from the template filename
-        let suggested_filename = suggest_output_filename(&args.template_filename.clone().unwrap());
-        workspace::generate_from_file(
-            template_filename,
-            suggested_filename,
-            &config,
-            env::consts::OS,
-        )
+        // Don't overwrite a template which might have been edited by hand
+        if PathBuf::from(&template_filename).exists() {
+            return Err(workspace::Error::Io(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("{} already exists", template_filename),
+            )));
+        }
+
+        std::fs::write(&template_filename, template_contents).map_err(workspace::Error::Io)?;
+        eprintln!("Template extracted to {}", template_filename);
+    }
+
+    Ok(())
+}
+
+/// The files which, when changed, require regenerating
+fn watched_files(template_filename: &str) -> Vec<PathBuf> {
+    let template_path = PathBuf::from(template_filename);
+    let mut files = vec![
+        template_path.clone(),
+        PathBuf::from(config::Config::filename()),
+    ];
+    files.extend(include::included_files(&template_path));
+    files
+}
+
+/// Regenerates on every change. Errors are printed and we keep watching.
+fn watch(args: &Args, template_filename: &str) -> ! {
+    let regenerate = || {
+        match load_configs(args) {
+            Ok(configs) => match generate_profiles(args, &configs, template_filename) {
+                Ok(_) if writes_files(args) => eprintln!("File generated successfully"),
+                Ok(_) => (),
+                Err(e) => diagnostics::print_error(&e, template_filename),
+            },
+            Err(e) => eprintln!("Config: {}", e),
+        }
+
+        watched_files(template_filename)
     };
 
-    match result {
-        Ok(_) => println!("File generated successfully"),
+    let files = regenerate();
+    eprintln!("Watching {} for changes...", template_filename);
+    watch::Watcher::new(files).run(regenerate)
+}
+
+fn main() {
+    // Handle -c, --create-project and -a, --create-template-project. Exits if handled.
+    handle_projects_usecase();
+
+    // Handle the main use case:
+
+    let args = Args::parse();
+    if let Err(e) = validate_args(&args) {
+        eprintln!("Error: {}", e);
+        process::exit(-1);
+    }
+
+    if let Some(workspace_filename) = &args.extract {
+        let result =
+            load_configs(&args).map(|configs| extract(&args, &configs[0], workspace_filename));
+        process::exit(match result {
+            Ok(Ok(_)) => 0,
+            Ok(Err(e)) => {
+                diagnostics::print_error(&e, workspace_filename);
+                -1
+            }
+            Err(e) => {
+                eprintln!("Config: {}", e);
+                -1
+            }
+        });
+    }
+
+    let template_filename = args
+        .template_filename
+        .clone()
+        .expect("You're expected to pass: -t <template_filename>");
+
+    if args.watch {
+        watch(&args, &template_filename);
+    }
+
+    let configs = match load_configs(&args) {
+        Ok(configs) => configs,
         Err(e) => {
-            match e {
-                workspace::Error::ExpectedRootObject => {
-                    eprintln!("Error: Expected root object in JSON file");
-                }
-                workspace::Error::Io(e) => {
-                    eprintln!("Error: {:?}", e);
-                }
-                workspace::Error::Json(e) => {
-                    eprintln!("Error: {:?}", e);
-                }
+            eprintln!("Config: {}", e);
+            process::exit(-1);
+        }
+    };
+
+    if args.check {
+        let result = configs.iter().try_fold(true, |up_to_date, config| {
+            Ok(check(&args, config, &template_filename)? && up_to_date)
+        });
+        match result {
+            Ok(true) => {
+                eprintln!("Generated files are up to date");
+                process::exit(0);
+            }
+            Ok(false) => process::exit(1),
+            Err(e) => {
+                diagnostics::print_error(&e, &template_filename);
+                process::exit(-1);
             }
+        }
+    }
 
+    match generate_profiles(&args, &configs, &template_filename) {
+        Ok(_) if writes_files(&args) => eprintln!("File generated successfully"),
+        Ok(_) => (),
+        Err(e) => {
+            diagnostics::print_error(&e, &template_filename);
             process::exit(-1);
         }
     }
@@ -194,7 +508,7 @@ mod cli_tests {
         set_test_env();
 
         // Test parsing of -a option
-        let args = Args::try_parse_from(&["vscode-workspace-gen", "-a", "depends"]).unwrap();
+        let args = Args::try_parse_from(["vscode-workspace-gen", "-a", "depends"]).unwrap();
         assert!(args.projects.create_template_project.is_some());
         assert_eq!(
             args.projects.create_template_project.unwrap().unwrap(),
@@ -202,9 +516,8 @@ mod cli_tests {
         );
 
         // Test parsing of --create-template-project option
-        let args =
-            Args::try_parse_from(&["vscode-workspace-gen", "--create-template-project", "a"])
-                .unwrap();
+        let args = Args::try_parse_from(["vscode-workspace-gen", "--create-template-project", "a"])
+            .unwrap();
         assert!(args.projects.create_template_project.is_some());
         assert_eq!(args.projects.create_template_project.unwrap().unwrap(), "a");
 
@@ -246,7 +559,7 @@ mod cli_tests {
         set_test_env();
 
         // Test single file project with -a option
-        let args = Args::try_parse_from(&["vscode-workspace-gen", "-a", "a"]).unwrap();
+        let args = Args::try_parse_from(["vscode-workspace-gen", "-a", "a"]).unwrap();
         assert!(args.projects.create_template_project.is_some());
 
         // Use a dedicated test directory to avoid conflicts
@@ -277,4 +590,196 @@ mod cli_tests {
         // Clean up
         std::fs::remove_dir_all(test_dir).unwrap();
     }
+
+    #[test]
+    fn test_check() {
+        let dir =
+            std::env::temp_dir().join(format!("vscode-workspace-gen-check-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let template = dir.join("foo.code-workspace.template");
+        let output = dir.join("foo.code-workspace");
+        std::fs::write(&template, r#"{ "gen.globals": { "a": 1 }, "b": "@{a}" }"#).unwrap();
+
+        let args = Args::try_parse_from([
+            "vscode-workspace-gen",
+            "-t",
+            template.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+            "--check",
+        ])
+        .unwrap();
+        assert!(args.check);
+        assert!(validate_args(&args).is_ok());
+
+        let config = config::Config::default();
+        let template_filename = args.template_filename.clone().unwrap();
+
+        // Missing output
+        assert!(!check(&args, &config, &template_filename).unwrap());
+        assert!(!output.exists());
+
+        generate(&args, &config, &template_filename).unwrap();
+        assert!(check(&args, &config, &template_filename).unwrap());
+
+        // Edited by hand
+        std::fs::write(&output, r#"{ "b": 2 }"#).unwrap();
+        assert!(!check(&args, &config, &template_filename).unwrap());
+        assert_eq!(std::fs::read_to_string(&output).unwrap(), r#"{ "b": 2 }"#);
+
+        // There's no file to compare stdout with
+        let args =
+            Args::try_parse_from(["vscode-workspace-gen", "-t", "foo", "-o", "-", "--check"])
+                .unwrap();
+        assert!(validate_args(&args).is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_check_several_oses() {
+        let dir = std::env::temp_dir().join(format!(
+            "vscode-workspace-gen-check-oses-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let template = dir.join("foo.code-workspace.template");
+        std::fs::write(
+            &template,
+            r#"{
+            "gen.globals": { "generator": { "gen.select_os": { "windows": "Ninja", "default": "Unix Makefiles" } } },
+            "settings": { "cmake.generator": "@{generator}" },
+            "gen.cmakepresets": {
+                "version": 3,
+                "configurePresets": [{ "name": "dev", "generator": "@{generator}" }]
+            }
+        }"#,
+        )
+        .unwrap();
+
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        let config: config::Config = serde_json::from_value(serde_json::json!({
+            "per_os_output_filenames": {
+                "linux": path("linux.code-workspace"),
+                "windows": path("windows.code-workspace")
+            },
+            "cmake_presets_filename": path("CMakePresets.json")
+        }))
+        .unwrap();
+
+        let args = Args::try_parse_from(["vscode-workspace-gen", "-t", template.to_str().unwrap()])
+            .unwrap();
+        let template_filename = args.template_filename.clone().unwrap();
+
+        // The presets are written once, so checking doesn't compare them with another OS's
+        generate(&args, &config, &template_filename).unwrap();
+        assert!(check(&args, &config, &template_filename).unwrap());
+        assert!(std::fs::read_to_string(dir.join("CMakePresets.json"))
+            .unwrap()
+            .contains("Unix Makefiles"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let dir = std::env::temp_dir().join(format!(
+            "vscode-workspace-gen-dry-run-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let template = dir.join("foo.code-workspace.template");
+        let output = dir.join("foo.code-workspace");
+        std::fs::write(&template, r#"{ "gen.globals": { "a": 1 }, "b": "@{a}" }"#).unwrap();
+
+        let args = Args::try_parse_from([
+            "vscode-workspace-gen",
+            "-t",
+            template.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+            "--dry-run",
+        ])
+        .unwrap();
+        assert!(!writes_files(&args));
+
+        let config = config::Config::default();
+        let template_filename = args.template_filename.clone().unwrap();
+        generate(&args, &config, &template_filename).unwrap();
+        assert!(!output.exists());
+
+        // Errors are still reported
+        std::fs::write(&template, r#"{ "b": "#).unwrap();
+        assert!(generate(&args, &config, &template_filename).is_err());
+
+        let args = Args::try_parse_from(["vscode-workspace-gen", "-t", "foo", "-o", "-"]).unwrap();
+        assert!(!writes_files(&args));
+        assert!(Args::try_parse_from(["vscode-workspace-gen", "--dry-run", "--check"]).is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_has_targets() {
+        let config: config::Config = serde_json::from_value(serde_json::json!({
+            "per_os_output_filenames": { "linux": "l.json", "windows": null },
+            "os_targets": ["windows", "macos"]
+        }))
+        .unwrap();
+
+        let args = Args::try_parse_from(["vscode-workspace-gen", "-t", "foo"]).unwrap();
+        assert!(has_targets(&args, &config).is_err());
+
+        let args = Args::try_parse_from(["vscode-workspace-gen", "-t", "foo", "-o", "x"]).unwrap();
+        assert!(has_targets(&args, &config).is_ok());
+
+        let args = Args::try_parse_from(["vscode-workspace-gen", "-t", "foo"]).unwrap();
+        assert!(has_targets(&args, &config::Config::default()).is_ok());
+    }
+
+    #[test]
+    fn test_define() {
+        let args = Args::try_parse_from([
+            "vscode-workspace-gen",
+            "-D",
+            "compiler=clang",
+            "--define",
+            "flags=-O2 -g=3",
+        ])
+        .unwrap();
+        assert_eq!(
+            args.defines,
+            vec![
+                ("compiler".to_string(), "clang".to_string()),
+                ("flags".to_string(), "-O2 -g=3".to_string())
+            ]
+        );
+
+        assert!(Args::try_parse_from(["vscode-workspace-gen", "-D", "compiler"]).is_err());
+        assert!(Args::try_parse_from(["vscode-workspace-gen", "-D", "=clang"]).is_err());
+    }
+
+    #[test]
+    fn test_profile_args() {
+        let args =
+            Args::try_parse_from(["vscode-workspace-gen", "-t", "foo", "--all-profiles"]).unwrap();
+        assert!(args.all_profiles);
+
+        let args = Args::try_parse_from(["vscode-workspace-gen", "--profile", "ci"]).unwrap();
+        assert_eq!(args.profile.as_deref(), Some("ci"));
+
+        assert!(Args::try_parse_from([
+            "vscode-workspace-gen",
+            "--profile",
+            "ci",
+            "--all-profiles"
+        ])
+        .is_err());
+        assert!(
+            Args::try_parse_from(["vscode-workspace-gen", "--all-profiles", "-o", "foo"]).is_err()
+        );
+    }
 }
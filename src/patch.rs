@@ -0,0 +1,222 @@
+// SPDX-License-Identifier: MIT
+
+// JSON Patch (RFC 6902), for "gen.patch", and the JSON pointers (RFC 6901) it's based on.
+// A patch is a list of operations, like { "op": "replace", "path": "/cargo/args/0", "value": "run" },
+// which are applied in order.
+
+use serde_json::Value;
+
+/// Applies a single operation of a JSON Patch to value.
+/// Returns a message describing the problem if the operation is invalid or can't be applied.
+pub fn apply_operation(value: &mut Value, operation: &Value) -> Result<(), String> {
+    let Some(operation) = operation.as_object() else {
+        return Err("Expected an object with \"op\" and \"path\"".to_string());
+    };
+
+    let member = |name: &str| {
+        operation
+            .get(name)
+            .ok_or_else(|| format!("Missing \"{}\"", name))
+    };
+    let pointer = |name: &str| {
+        member(name)?
+            .as_str()
+            .ok_or_else(|| format!("\"{}\" should be a JSON pointer", name))
+    };
+
+    let path = pointer("path")?;
+    match member("op")?.as_str().unwrap_or_default() {
+        "add" => add(value, path, member("value")?.clone()),
+        "remove" => remove(value, path).map(|_| ()),
+        "replace" => {
+            let target = value
+                .pointer_mut(path)
+                .ok_or_else(|| format!("Nothing to replace at {}", path))?;
+            *target = member("value")?.clone();
+            Ok(())
+        }
+        "move" => {
+            let from = pointer("from")?;
+            if path.starts_with(&format!("{}/", from)) {
+                return Err(format!("Can't move {} into itself", from));
+            }
+            let moved = remove(value, from)?;
+            add(value, path, moved)
+        }
+        "copy" => {
+            let from = pointer("from")?;
+            let copied = value
+                .pointer(from)
+                .ok_or_else(|| format!("Nothing to copy at {}", from))?
+                .clone();
+            add(value, path, copied)
+        }
+        "test" => {
+            if value.pointer(path) == Some(member("value")?) {
+                Ok(())
+            } else {
+                Err(format!("Test of {} failed", path))
+            }
+        }
+        op => Err(format!(
+            "Unknown op `{}`, expected add, remove, replace, move, copy or test",
+            op
+        )),
+    }
+}
+
+/// Splits a JSON pointer into its parent's pointer and its last, unescaped, token.
+/// None for the root pointer.
+fn split(path: &str) -> Result<Option<(&str, String)>, String> {
+    if path.is_empty() {
+        return Ok(None);
+    }
+
+    let (parent, token) = path
+        .rsplit_once('/')
+        .ok_or_else(|| format!("Invalid JSON pointer {}, it should start with /", path))?;
+
+    if !parent.is_empty() && !parent.starts_with('/') {
+        return Err(format!(
+            "Invalid JSON pointer {}, it should start with /",
+            path
+        ));
+    }
+
+    Ok(Some((parent, token.replace("~1", "/").replace("~0", "~"))))
+}
+
+/// Parses an array index. "-" is the end of the array.
+fn array_index(token: &str, len: usize) -> Option<usize> {
+    if token == "-" {
+        return Some(len);
+    }
+
+    // Leading zeros aren't allowed
+    if token.len() > 1 && token.starts_with('0') {
+        return None;
+    }
+
+    token.parse().ok()
+}
+
+fn add(value: &mut Value, path: &str, new_value: Value) -> Result<(), String> {
+    let Some((parent, token)) = split(path)? else {
+        *value = new_value;
+        return Ok(());
+    };
+
+    match value.pointer_mut(parent) {
+        Some(Value::Object(obj)) => {
+            obj.insert(token, new_value);
+            Ok(())
+        }
+        Some(Value::Array(array)) => match array_index(&token, array.len()) {
+            Some(index) if index <= array.len() => {
+                array.insert(index, new_value);
+                Ok(())
+            }
+            _ => Err(format!("Invalid array index {}", path)),
+        },
+        Some(_) => Err(format!(
+            "Can't add {}, as its parent isn't a container",
+            path
+        )),
+        None => Err(format!("Can't add {}, as its parent doesn't exist", path)),
+    }
+}
+
+/// Removes the value at path, and returns it
+fn remove(value: &mut Value, path: &str) -> Result<Value, String> {
+    let Some((parent, token)) = split(path)? else {
+        return Err("Can't remove the whole object".to_string());
+    };
+
+    let removed = match value.pointer_mut(parent) {
+        Some(Value::Object(obj)) => obj.shift_remove(&token),
+        Some(Value::Array(array)) => array_index(&token, array.len())
+            .filter(|index| *index < array.len())
+            .map(|index| array.remove(index)),
+        _ => None,
+    };
+
+    removed.ok_or_else(|| format!("Nothing to remove at {}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn patch(mut value: Value, operations: Value) -> Result<Value, String> {
+        for operation in operations.as_array().unwrap() {
+            apply_operation(&mut value, operation)?;
+        }
+        Ok(value)
+    }
+
+    #[test]
+    fn test_apply_operation() {
+        let value = json!({ "a": { "b": [1, 2, 3] }, "c/d": "e" });
+
+        assert_eq!(
+            patch(
+                value.clone(),
+                json!([
+                    { "op": "add", "path": "/a/b/-", "value": 4 },
+                    { "op": "add", "path": "/a/b/0", "value": 0 },
+                    { "op": "remove", "path": "/a/b/2" },
+                    { "op": "replace", "path": "/c~1d", "value": "f" },
+                    { "op": "test", "path": "/c~1d", "value": "f" },
+                    { "op": "copy", "from": "/a/b", "path": "/g" },
+                    { "op": "move", "from": "/a", "path": "/h" }
+                ])
+            ),
+            Ok(json!({ "c/d": "f", "g": [0, 1, 3, 4], "h": { "b": [0, 1, 3, 4] } }))
+        );
+
+        assert_eq!(
+            patch(
+                value.clone(),
+                json!([{ "op": "add", "path": "", "value": 1 }])
+            ),
+            Ok(json!(1))
+        );
+    }
+
+    #[test]
+    fn test_apply_operation_errors() {
+        let value = json!({ "a": { "b": [1, 2, 3] } });
+        let error = |operation: Value| apply_operation(&mut value.clone(), &operation).unwrap_err();
+
+        assert_eq!(
+            error(json!({ "op": "remove", "path": "/x" })),
+            "Nothing to remove at /x"
+        );
+        assert_eq!(
+            error(json!({ "op": "add", "path": "/a/b/5", "value": 1 })),
+            "Invalid array index /a/b/5"
+        );
+        assert_eq!(
+            error(json!({ "op": "add", "path": "/x/y", "value": 1 })),
+            "Can't add /x/y, as its parent doesn't exist"
+        );
+        assert_eq!(
+            error(json!({ "op": "test", "path": "/a/b/0", "value": 2 })),
+            "Test of /a/b/0 failed"
+        );
+        assert_eq!(
+            error(json!({ "op": "move", "from": "/a", "path": "/a/c" })),
+            "Can't move /a into itself"
+        );
+        assert_eq!(error(json!({ "op": "replace" })), "Missing \"path\"");
+        assert_eq!(
+            error(json!({ "op": "remove", "path": "a" })),
+            "Invalid JSON pointer a, it should start with /"
+        );
+        assert_eq!(
+            error(json!({ "op": "merge", "path": "/a" })),
+            "Unknown op `merge`, expected add, remove, replace, move, copy or test"
+        );
+    }
+}
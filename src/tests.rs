@@ -1,6 +1,6 @@
 // SPDX-License-Identifier: MIT
 
-use crate::{config::Config, workspace::*};
+use crate::{config::Config, diagnostics::Diagnostics, workspace::*};
 use serde_json::Value;
 
 #[test]
@@ -10,6 +10,7 @@ fn test_unknown_file() {
         "unknown".to_string(),
         &Config::default(),
         std::env::consts::OS,
+        &mut Vec::new(),
     );
     assert!(result.is_err());
     match result {
@@ -235,6 +236,300 @@ fn test_inline_object_expand_priorities() {
     assert_eq!(result, expected);
 }
 
+#[test]
+fn test_deep_merge() {
+    let template = |strategy: &str| {
+        format!(
+            r#"{{
+            "gen.globals": {{
+                "cargo_base": {{
+                    "type": "lldb",
+                    "cargo": {{
+                        "args": ["build", "--bin=app"],
+                        "filter": {{ "name": "app", "kind": "bin" }}
+                    }},
+                    "env": ["A=1"]
+                }}
+            }},
+            "launch": {{
+                "cargo": {{
+                    "args": ["--bin=app", "--release"],
+                    "filter": {{ "kind": "example" }}
+                }},
+                "@@@{{cargo_base}}": "{}",
+                "name": "app"
+            }}
+        }}"#,
+            strategy
+        )
+    };
+
+    let generate = |strategy: &str| {
+        let result = generate_from_string(&template(strategy), std::env::consts::OS).unwrap();
+        serde_json::to_string(&result["launch"]).unwrap()
+    };
+
+    // Nested objects are merged, and the object's keys win
+    assert_eq!(
+        generate(""),
+        r#"{"cargo":{"args":["--bin=app","--release"],"filter":{"name":"app","kind":"example"}},"type":"lldb","env":["A=1"],"name":"app"}"#
+    );
+    assert_eq!(generate("replace"), generate(""));
+    assert!(generate("append").contains(r#""args":["build","--bin=app","--bin=app","--release"]"#));
+    assert!(generate("prepend").contains(r#""args":["--bin=app","--release","build","--bin=app"]"#));
+    assert!(generate("union").contains(r#""args":["build","--bin=app","--release"]"#));
+
+    let result = generate_from_string(&template("merge"), std::env::consts::OS);
+    assert!(matches!(
+        result,
+        Err(Error::InvalidDirective { json_pointer, .. }) if json_pointer == "/launch/@@@{cargo_base}"
+    ));
+}
+
+#[test]
+fn test_deep_merge_several_bases() {
+    let template = r#"{
+        "gen.globals": {
+            "a": { "cargo": { "args": ["a"], "filter": { "kind": "bin" } }, "type": "lldb" },
+            "b": { "cargo": { "env": "x", "args": ["b"] } }
+        },
+        "bases": { "@@@{a}": "", "@@@{b}": "append" },
+        "own": { "cargo": { "args": ["own"] }, "@@@{a}": "", "@@@{b}": "" }
+    }"#;
+
+    let result = generate_from_string(template, std::env::consts::OS).unwrap();
+
+    // The bases are merged with each other in order, then the object's keys over them
+    assert_eq!(
+        serde_json::to_string(&result["bases"]).unwrap(),
+        r#"{"cargo":{"args":["a","b"],"filter":{"kind":"bin"},"env":"x"},"type":"lldb"}"#
+    );
+    assert_eq!(
+        serde_json::to_string(&result["own"]).unwrap(),
+        r#"{"cargo":{"args":["own"],"filter":{"kind":"bin"},"env":"x"},"type":"lldb"}"#
+    );
+}
+
+#[test]
+fn test_extends() {
+    let template = r#"{
+        "gen.globals": {
+            "debugger": { "type": "lldb", "request": "launch", "env": { "A": "1" } },
+            "cargo_base": {
+                "gen.extends": "debugger",
+                "cargo": { "args": ["build"] },
+                "env": { "B": "2" }
+            },
+            "release": { "cargo": { "args": ["build", "--release"] } }
+        },
+        "configurations": [
+            { "gen.extends": "cargo_base", "name": "debug", "request": "attach" },
+            { "gen.extends": ["cargo_base", "release"], "name": "release" }
+        ]
+    }"#;
+
+    let expected: Value = serde_json::from_str(
+        r#"{
+        "configurations": [
+            {
+                "type": "lldb", "request": "attach", "env": { "A": "1", "B": "2" },
+                "cargo": { "args": ["build"] }, "name": "debug"
+            },
+            {
+                "type": "lldb", "request": "launch", "env": { "A": "1", "B": "2" },
+                "cargo": { "args": ["build", "--release"] }, "name": "release"
+            }
+        ]
+    }"#,
+    )
+    .unwrap();
+
+    let result = generate_from_string(&String::from(template), std::env::consts::OS).unwrap();
+    assert_eq!(
+        serde_json::to_string(&result).unwrap(),
+        serde_json::to_string(&expected).unwrap()
+    );
+
+    let result = generate_from_string(
+        &String::from(r#"{ "gen.globals": { "a": [1] }, "b": { "gen.extends": "a" } }"#),
+        std::env::consts::OS,
+    );
+    assert!(matches!(
+        result,
+        Err(Error::InvalidDirective { json_pointer, .. }) if json_pointer == "/b/gen.extends"
+    ));
+
+    let result = generate_from_string(
+        &String::from(
+            r#"{ "gen.globals": { "a": { "gen.extends": "b" }, "b": { "gen.extends": "a" } } }"#,
+        ),
+        std::env::consts::OS,
+    );
+    assert!(matches!(result, Err(Error::CyclicGlobals(_))));
+}
+
+#[test]
+fn test_remove_and_patch() {
+    let template = r#"{
+        "gen.globals": {
+            "lldb_base": {
+                "type": "lldb",
+                "sourceLanguages": ["rust"],
+                "cargo": { "args": ["build", "--bin=app"], "filter": { "kind": "bin" } }
+            }
+        },
+        "launch": {
+            "@@{lldb_base}": "",
+            "gen.remove": ["sourceLanguages", "/cargo/filter", "missing"],
+            "gen.patch": [
+                { "op": "replace", "path": "/cargo/args/1", "value": "--bin=tool" },
+                { "op": "add", "path": "/cargo/args/-", "value": "--release" }
+            ]
+        }
+    }"#;
+
+    let mut diagnostics = Diagnostics::default();
+    let result = generate_from_template(
+        template,
+        None,
+        &Config::default(),
+        std::env::consts::OS,
+        &mut diagnostics,
+    )
+    .unwrap()
+    .workspace;
+    assert_eq!(
+        serde_json::to_string(&result).unwrap(),
+        r#"{"launch":{"type":"lldb","cargo":{"args":["build","--bin=tool","--release"]}}}"#
+    );
+
+    let warnings: Vec<&str> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+    assert_eq!(warnings, vec!["gen.remove: nothing to remove at /missing"]);
+
+    let result = generate_from_string(
+        &String::from(r#"{ "a": { "gen.patch": [{ "op": "remove", "path": "/b" }] } }"#),
+        std::env::consts::OS,
+    );
+    assert!(matches!(
+        result,
+        Err(Error::InvalidDirective { json_pointer, .. }) if json_pointer == "/a/gen.patch/0"
+    ));
+}
+
+#[test]
+fn test_foreach() {
+    let template = r#"{
+        "gen.globals": {
+            "test_binaries": [
+                { "name": "tst_a", "args": ["-v"], "suites": ["x", "y"] },
+                { "name": "tst_b", "args": [], "suites": [] }
+            ]
+        },
+        "configurations": [
+            {
+                "gen.foreach": "@{test_binaries}",
+                "gen.as": "bin",
+                "gen.index_as": "i",
+                "gen.template": {
+                    "name": "${i}: ${bin.name}",
+                    "program": "${workspaceFolder}/build/${bin.name}",
+                    "args": "${bin.args}"
+                }
+            },
+            "last"
+        ],
+        "tasks": [
+            {
+                "gen.foreach": "@{test_binaries}",
+                "gen.as": "bin",
+                "gen.template": {
+                    "label": "${bin.name}",
+                    "suites": [
+                        { "gen.foreach": "${bin.suites}", "gen.template": "${bin.name}:${item}" }
+                    ]
+                }
+            }
+        ]
+    }"#;
+
+    let expected: Value = serde_json::from_str(
+        r#"{
+        "configurations": [
+            { "name": "0: tst_a", "program": "${workspaceFolder}/build/tst_a", "args": ["-v"] },
+            { "name": "1: tst_b", "program": "${workspaceFolder}/build/tst_b", "args": [] },
+            "last"
+        ],
+        "tasks": [
+            { "label": "tst_a", "suites": ["tst_a:x", "tst_a:y"] },
+            { "label": "tst_b", "suites": [] }
+        ]
+    }"#,
+    )
+    .unwrap();
+
+    let result = generate_from_string(&String::from(template), std::env::consts::OS).unwrap();
+    assert_eq!(result, expected);
+
+    // A nested loop rebinding the outer loop's name gets its own values
+    let template = r#"{
+        "gen.globals": { "list": [{ "name": "a", "sub": [1, 2] }] },
+        "l": [
+            {
+                "gen.foreach": "@{list}",
+                "gen.index_as": "i",
+                "gen.template": {
+                    "s": [{ "gen.foreach": "${item.sub}", "gen.template": "${item}" }],
+                    "t": [{ "gen.foreach": "${item.sub}", "gen.as": "x", "gen.index_as": "i", "gen.template": "${i}:${item.name}" }],
+                    "n": "${item.name}"
+                }
+            }
+        ]
+    }"#;
+    let result = generate_from_string(&String::from(template), std::env::consts::OS).unwrap();
+    assert_eq!(
+        result["l"],
+        serde_json::json!([{ "s": [1, 2], "t": ["0:a", "1:a"], "n": "a" }])
+    );
+
+    let result = generate_from_string(
+        &String::from(r#"{ "a": [1, { "gen.foreach": "not a list", "gen.template": 1 }] }"#),
+        std::env::consts::OS,
+    );
+    assert!(matches!(
+        result,
+        Err(Error::InvalidDirective { json_pointer, .. }) if json_pointer == "/a/1/gen.foreach"
+    ));
+
+    let result = generate_from_string(
+        &String::from(r#"{ "a": [{ "gen.foreach": [1], "gen.templat": 1 }] }"#),
+        std::env::consts::OS,
+    );
+    assert!(matches!(
+        result,
+        Err(Error::InvalidDirective { json_pointer, .. }) if json_pointer == "/a/0/gen.templat"
+    ));
+
+    // A template which is a loop itself generates several items
+    let result = generate_from_string(
+        &String::from(
+            r#"{ "a": [{ "gen.foreach": [[1, 2], [3]], "gen.template": { "gen.foreach": "${item}", "gen.template": "${item}" } }] }"#,
+        ),
+        std::env::consts::OS,
+    )
+    .unwrap();
+    assert_eq!(result["a"], serde_json::json!([1, 2, 3]));
+
+    // Outside of an array, there's nowhere to put the items
+    let result = generate_from_string(
+        &String::from(r#"{ "a": { "gen.foreach": [1], "gen.template": 1 } }"#),
+        std::env::consts::OS,
+    );
+    assert!(matches!(
+        result,
+        Err(Error::InvalidDirective { json_pointer, .. }) if json_pointer == "/a/gen.foreach"
+    ));
+}
+
 #[test]
 #[cfg(target_os = "linux")]
 fn test_gen_os() {
@@ -326,18 +621,25 @@ fn test_token_kind() {
         token_kind_from_str("@@{key}"),
         TokenKind::Inplace("key".to_string())
     );
+    assert_eq!(
+        token_kind_from_str("@@@{key}"),
+        TokenKind::Merge("key".to_string())
+    );
     assert_eq!(token_kind_from_str("@{}"), TokenKind::None);
     assert_eq!(token_kind_from_str("@@{}"), TokenKind::None);
+    assert_eq!(token_kind_from_str("@@@{}"), TokenKind::None);
     assert_eq!(token_kind_from_str("@{"), TokenKind::None);
     assert_eq!(token_kind_from_str("key}"), TokenKind::None);
     assert_eq!(token_kind_from_str("key"), TokenKind::None);
+    assert_eq!(token_kind_from_str("@{a} @{b}"), TokenKind::None);
     assert_eq!(token_kind_from_str(""), TokenKind::None);
 }
 
 /// tests is_allowed_in_os
 #[test]
 fn test_is_allowed_in_os() {
-    if cfg!(linux) {
+    let os_groups = Config::default().os_groups();
+    if cfg!(target_os = "linux") {
         let value1 = serde_json::json!({
             "gen.os": "windows"
         });
@@ -346,8 +648,8 @@ fn test_is_allowed_in_os() {
             "gen.os": "linux"
         });
 
-        assert!(!is_allowed_in_os(&value1, std::env::consts::OS));
-        assert!(is_allowed_in_os(&value2, std::env::consts::OS));
+        assert!(!is_allowed_in_os(&value1, std::env::consts::OS, &os_groups));
+        assert!(is_allowed_in_os(&value2, std::env::consts::OS, &os_groups));
     } else if cfg!(windows) {
         let value1 = serde_json::json!({
             "gen.os": "windows"
@@ -357,8 +659,8 @@ fn test_is_allowed_in_os() {
             "gen.os": "linux"
         });
 
-        assert!(is_allowed_in_os(&value1, std::env::consts::OS));
-        assert!(!is_allowed_in_os(&value2, std::env::consts::OS));
+        assert!(is_allowed_in_os(&value1, std::env::consts::OS, &os_groups));
+        assert!(!is_allowed_in_os(&value2, std::env::consts::OS, &os_groups));
     }
 }
 
@@ -387,3 +689,1170 @@ fn test_replace_env_vars() {
     let result = generate_from_string(&String::from(template), std::env::consts::OS).unwrap();
     assert_eq!(result, expected);
 }
+
+#[test]
+fn test_globals_referencing_globals() {
+    let template = r#"{
+        "gen.globals": {
+            "lldb_base": {
+                "type": "lldb",
+                "cargo": {
+                    "filter": "@{cargo_filter}"
+                },
+                "@@{common}": ""
+            },
+            "cargo_filter": {
+                "kind": "bin"
+            },
+            "common": {
+                "cwd": "${workspaceFolder}",
+                "args": ["@@{args}"]
+            },
+            "args": [1, 2]
+        },
+        "launch": "@{lldb_base}"
+    }"#;
+
+    let expected: Value = serde_json::from_str(
+        r#"{
+        "launch": {
+            "type": "lldb",
+            "cargo": {
+                "filter": {
+                    "kind": "bin"
+                }
+            },
+            "cwd": "${workspaceFolder}",
+            "args": [1, 2]
+        }
+    }"#,
+    )
+    .unwrap();
+
+    let result = generate_from_string(&String::from(template), std::env::consts::OS).unwrap();
+    assert_eq!(result, expected);
+}
+
+#[test]
+fn test_globals_cycle() {
+    let template = r#"{
+        "gen.globals": {
+            "a": { "b": "@{b}" },
+            "b": [ "@@{c}" ],
+            "c": { "@@{a}": "" }
+        },
+        "obj": "@{a}"
+    }"#;
+
+    match generate_from_string(&String::from(template), std::env::consts::OS) {
+        Err(Error::CyclicGlobals(chain)) => assert_eq!(chain, vec!["a", "b", "c", "a"]),
+        _ => panic!("Expected CyclicGlobals"),
+    }
+
+    let template = r#"{
+        "gen.globals": {
+            "a": [ "@{a}" ]
+        }
+    }"#;
+
+    match generate_from_string(&String::from(template), std::env::consts::OS) {
+        Err(Error::CyclicGlobals(chain)) => assert_eq!(chain, vec!["a", "a"]),
+        _ => panic!("Expected CyclicGlobals"),
+    }
+}
+
+fn test_data_path(filename: &str) -> std::path::PathBuf {
+    let mut path = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    path.push("test_data");
+    path.push(filename);
+    path
+}
+
+fn generate_from_test_file(filename: &str) -> Result<Value, Error> {
+    let path = test_data_path(filename);
+    let contents = std::fs::read_to_string(&path).unwrap();
+    generate_from_template(
+        &contents,
+        Some(&path),
+        &Config::default(),
+        std::env::consts::OS,
+        &mut Diagnostics::default(),
+    )
+    .map(|generated| generated.workspace)
+}
+
+#[test]
+fn test_cargo_metadata() {
+    let expected: Value = serde_json::from_str(
+        r#"{
+        "launch": {
+            "configurations": [
+                {
+                    "type": "lldb", "request": "launch", "name": "app",
+                    "cargo": { "args": ["build", "--bin=app", "--package=app"] }
+                },
+                {
+                    "type": "lldb", "request": "launch", "name": "app-cli",
+                    "cargo": { "args": ["build", "--bin=app-cli", "--package=app"] }
+                }
+            ]
+        },
+        "settings": {
+            "rust-analyzer.linkedProjects": ["/ws/core/Cargo.toml"]
+        }
+    }"#,
+    )
+    .unwrap();
+
+    let result = generate_from_test_file("cargo/template.json").unwrap();
+    assert_eq!(result, expected);
+
+    let result = generate_from_string(
+        &String::from(r#"{ "gen.cargo_metadata": "missing.json" }"#),
+        std::env::consts::OS,
+    );
+    assert!(matches!(
+        result,
+        Err(Error::InvalidDirective { json_pointer, .. }) if json_pointer == "/gen.cargo_metadata"
+    ));
+}
+
+#[test]
+fn test_cmake_file_api() {
+    let expected: Value = serde_json::from_str(
+        r#"{
+        "launch": {
+            "configurations": [
+                {
+                    "type": "cppdbg", "request": "launch", "name": "app",
+                    "program": "/ws/build/bin/app", "cwd": "/ws"
+                },
+                {
+                    "type": "cppdbg", "request": "launch", "name": "tst_core",
+                    "program": "/ws/build/tests/tst_core", "cwd": "/ws/tests"
+                }
+            ]
+        }
+    }"#,
+    )
+    .unwrap();
+
+    let result = generate_from_test_file("cmake/template.json").unwrap();
+    assert_eq!(result, expected);
+
+    // Paths from a Windows build are absolute on every OS
+    let result = generate_from_test_file("cmake/template-windows.json").unwrap();
+    let programs: Vec<&str> = result["launch"]["configurations"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|configuration| configuration["program"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        programs,
+        vec![
+            "C:/ws/out/bin/Debug/app.exe",
+            "C:/ws/build/tests/Debug/tst_core.exe"
+        ]
+    );
+
+    let result = generate_from_string(
+        &String::from(r#"{ "gen.cmake_build_dir": "no-such-build-dir" }"#),
+        std::env::consts::OS,
+    );
+    match result {
+        Err(Error::InvalidDirective {
+            message,
+            json_pointer,
+            ..
+        }) => {
+            assert_eq!(json_pointer, "/gen.cmake_build_dir");
+            assert!(message.starts_with("No CMake File API reply in"));
+        }
+        _ => panic!("Expected InvalidDirective"),
+    }
+}
+
+#[test]
+fn test_dotted_references() {
+    let template = r#"{
+        "gen.globals": {
+            "build": { "dir": "build-dev", "jobs": [4, 8] },
+            "build.dir": "overridden",
+            "path": "@{build.jobs.1}"
+        },
+        "a": "@{build.dir}",
+        "b": "@{path}",
+        "c": { "gen.if": "build.jobs.0 == 4", "d": 1 }
+    }"#;
+
+    let result = generate_from_string(&String::from(template), std::env::consts::OS).unwrap();
+    assert_eq!(
+        serde_json::to_string(&result).unwrap(),
+        r#"{"a":"overridden","b":8,"c":{"d":1}}"#
+    );
+}
+
+#[test]
+fn test_include() {
+    let expected: Value = serde_json::from_str(
+        r#"{
+        "settings": {
+            "editor.formatOnSave": true,
+            "files.trimTrailingWhitespace": true
+        },
+        "launch": {
+            "name": "main",
+            "filter": {
+                "kind": "bin"
+            },
+            "type": "lldb"
+        }
+    }"#,
+    )
+    .unwrap();
+
+    let result = generate_from_test_file("includes/main.template.json").unwrap();
+    assert_eq!(result, expected);
+}
+
+#[test]
+fn test_include_cycle() {
+    match generate_from_test_file("includes/cycle_a.json") {
+        Err(Error::IncludeCycle(chain)) => {
+            assert_eq!(chain.len(), 3);
+            assert!(chain[0].ends_with("cycle_a.json"));
+            assert!(chain[1].ends_with("cycle_b.json"));
+            assert!(chain[2].ends_with("cycle_a.json"));
+        }
+        _ => panic!("Expected IncludeCycle"),
+    }
+}
+
+#[test]
+fn test_include_errors() {
+    match generate_from_test_file("includes/includes_bad.json") {
+        Err(Error::Include { filename, error }) => {
+            assert!(filename.ends_with("bad.json"));
+            assert!(matches!(*error, Error::Json(_)));
+        }
+        _ => panic!("Expected Include error"),
+    }
+
+    let template = r#"{ "gen.include": "does_not_exist.json" }"#;
+    match generate_from_string(template, std::env::consts::OS) {
+        Err(Error::Include { filename, error }) => {
+            assert!(filename.ends_with("does_not_exist.json"));
+            assert!(matches!(*error, Error::Io(_)));
+        }
+        _ => panic!("Expected Include error"),
+    }
+
+    let template = r#"{ "gen.include": 1 }"#;
+    assert!(matches!(
+        generate_from_string(template, std::env::consts::OS),
+        Err(Error::InvalidDirective { .. })
+    ));
+}
+
+#[test]
+fn test_parameterized_globals() {
+    let template = r#"{
+        "gen.globals": {
+            "cargo_launch": {
+                "gen.params": ["name", "bin"],
+                "type": "lldb",
+                "name": "${name}",
+                "cargo": {
+                    "args": ["build", "--bin=${bin}"]
+                },
+                "cwd": "${workspaceFolder}"
+            },
+            "with_defaults": {
+                "gen.params": { "port": 8080, "host": "localhost" },
+                "port": "${port}",
+                "url": "http://${host}:${port}",
+                "${host}_enabled": true
+            }
+        },
+        "configurations": [
+            "@{cargo_launch(name=server, bin=srv)}",
+            "@{cargo_launch(name=client, bin=cli)}"
+        ],
+        "defaults": "@{with_defaults}",
+        "overridden": {
+            "@@{with_defaults(host=example.org)}": ""
+        }
+    }"#;
+
+    let expected: Value = serde_json::from_str(
+        r#"{
+        "configurations": [
+            {
+                "type": "lldb",
+                "name": "server",
+                "cargo": {
+                    "args": ["build", "--bin=srv"]
+                },
+                "cwd": "${workspaceFolder}"
+            },
+            {
+                "type": "lldb",
+                "name": "client",
+                "cargo": {
+                    "args": ["build", "--bin=cli"]
+                },
+                "cwd": "${workspaceFolder}"
+            }
+        ],
+        "defaults": {
+            "port": 8080,
+            "url": "http://localhost:8080",
+            "localhost_enabled": true
+        },
+        "overridden": {
+            "port": 8080,
+            "url": "http://example.org:8080",
+            "example.org_enabled": true
+        }
+    }"#,
+    )
+    .unwrap();
+
+    let result = generate_from_string(template, std::env::consts::OS).unwrap();
+    assert_eq!(result, expected);
+}
+
+#[test]
+fn test_parameterized_globals_errors() {
+    let template = r#"{
+        "gen.globals": {
+            "launch": { "gen.params": ["name"], "name": "${name}" },
+            "plain": { "a": 1 }
+        },
+        "l": "@{REFERENCE}"
+    }"#;
+
+    for reference in [
+        "launch",
+        "launch(name=a, foo=b)",
+        "launch(name)",
+        "plain(a=1)",
+    ] {
+        let template = template.replace("REFERENCE", reference);
+        assert!(
+            matches!(
+                generate_from_string(&template, std::env::consts::OS),
+                Err(Error::InvalidDirective { .. })
+            ),
+            "{}",
+            reference
+        );
+    }
+}
+
+#[test]
+fn test_string_interpolation() {
+    let template = r#"{
+        "gen.globals": {
+            "preset": "dev",
+            "jobs": 8,
+            "bin_dir": "${workspaceFolder}/build-@{preset}/bin",
+            "args": ["a"]
+        },
+        "obj": {
+            "program": "@{bin_dir}/app",
+            "args": "-j@{jobs} --preset=@{preset}",
+            "unknown": "@{unknown}-suffix",
+            "inplace": "@@{preset}-suffix",
+            "settings-@{preset}": true
+        }
+    }"#;
+
+    let expected: Value = serde_json::from_str(
+        r#"{
+        "obj": {
+            "program": "${workspaceFolder}/build-dev/bin/app",
+            "args": "-j8 --preset=dev",
+            "unknown": "@{unknown}-suffix",
+            "inplace": "@@{preset}-suffix",
+            "settings-dev": true
+        }
+    }"#,
+    )
+    .unwrap();
+
+    let result = generate_from_string(template, std::env::consts::OS).unwrap();
+    assert_eq!(result, expected);
+
+    let template = r#"{
+        "gen.globals": {
+            "args": ["a"]
+        },
+        "obj": "--args=@{args}"
+    }"#;
+
+    match generate_from_string(template, std::env::consts::OS) {
+        Err(Error::CannotInterpolate { key, json_pointer }) => {
+            assert_eq!(key, "args");
+            assert_eq!(json_pointer, "/obj");
+        }
+        _ => panic!("Expected CannotInterpolate"),
+    }
+}
+
+fn generate_strict(template: &str) -> Result<Value, Error> {
+    let mut config = Config::default();
+    config.set_strict(true);
+    generate_from_template(
+        template,
+        None,
+        &config,
+        std::env::consts::OS,
+        &mut Diagnostics::default(),
+    )
+    .map(|generated| generated.workspace)
+}
+
+#[test]
+fn test_strict() {
+    let cases = [
+        (r#"{ "a": { "b": "@{missing}" } }"#, "missing", "/a/b"),
+        (r#"{ "a": [1, "@@{missing}"] }"#, "missing", "/a/1"),
+        (
+            r#"{ "a": { "@@{missing}": "" } }"#,
+            "missing",
+            "/a/@@{missing}",
+        ),
+        (r#"{ "a/b": "x-@{missing}" }"#, "missing", "/a~1b"),
+        (
+            r#"{ "gen.globals": { "g": ["@{missing}"] } }"#,
+            "missing",
+            "/gen.globals/g/0",
+        ),
+        (
+            r#"{ "gen.globals": { "list": [1] }, "a": { "@@{list}": "" } }"#,
+            "list",
+            "/a/@@{list}",
+        ),
+        (
+            r#"{ "a": ["$${VSCODE_WORKSPACE_GEN_UNSET_VAR}"] }"#,
+            "VSCODE_WORKSPACE_GEN_UNSET_VAR",
+            "/a/0",
+        ),
+    ];
+
+    for (template, expected_key, expected_pointer) in cases {
+        // Not strict by default
+        assert!(generate_from_string(template, std::env::consts::OS).is_ok());
+
+        match generate_strict(template) {
+            Err(Error::Unresolved { key, json_pointer }) => {
+                assert_eq!(key, expected_key);
+                assert_eq!(json_pointer, expected_pointer);
+            }
+            _ => panic!("Expected Unresolved for {}", template),
+        }
+    }
+
+    let template = r#"{
+        "gen.globals": { "name": "foo" },
+        "a": "@{name}"
+    }"#;
+    assert!(generate_strict(template).is_ok());
+}
+
+#[test]
+fn test_warnings_have_locations() {
+    let template = r#"{
+        "launch": {
+            "configurations": [
+                {},
+                { "cargo": { "filter": "@{missing}" } },
+                { "@@{list}": "" }
+            ]
+        },
+        "gen.globals": {
+            "list": [1]
+        },
+        "other": "@{a(b)}"
+    }"#;
+
+    // Warnings found before the error are still reported
+    let mut diagnostics = Diagnostics::default();
+    let result = generate_from_template(
+        template,
+        None,
+        &Config::default(),
+        std::env::consts::OS,
+        &mut diagnostics,
+    );
+    match result {
+        Err(Error::InvalidDirective { json_pointer, .. }) => assert_eq!(json_pointer, "/other"),
+        _ => panic!("Expected InvalidDirective"),
+    }
+
+    let pointers: Vec<_> = diagnostics
+        .iter()
+        .map(|d| d.json_pointer.clone().unwrap())
+        .collect();
+    assert_eq!(
+        pointers,
+        vec![
+            "/launch/configurations/1/cargo/filter",
+            "/launch/configurations/2/@@{list}"
+        ]
+    );
+
+    let rendered = diagnostics
+        .iter()
+        .next()
+        .unwrap()
+        .render("t.json", Some(template));
+    assert!(rendered.starts_with("warning: No replacement found for key: missing"));
+    assert!(rendered.contains("--> t.json:5:30"), "{}", rendered);
+}
+
+#[test]
+fn test_key_order_is_preserved() {
+    let template = r#"{
+        "gen.globals": {
+            "g": {
+                "gen.description": "",
+                "gen.params": ["p"],
+                "x": "${p}",
+                "y": 2
+            }
+        },
+        "a": { "gen.os": ["linux", "windows", "macos"], "b": 1, "c": 2, "d": 3 },
+        "e": "@{g(p=1)}",
+        "f": 3
+    }"#;
+
+    let result = generate_from_string(template, std::env::consts::OS).unwrap();
+    assert_eq!(
+        serde_json::to_string(&result).unwrap(),
+        r#"{"a":{"b":1,"c":2,"d":3},"e":{"x":"1","y":2},"f":3}"#
+    );
+}
+
+#[test]
+fn test_jsonc() {
+    let template = r#"{
+        // Comments and trailing commas, like in VS Code's own files
+        "gen.globals": {
+            "lldb": {
+                "type": "lldb", // trailing
+                "request": "launch",
+            },
+        },
+        /* block */
+        "launch": { "configurations": ["@{lldb}",] },
+        "b": "@{missing}",
+    }"#;
+
+    let result = generate_from_string(template, std::env::consts::OS).unwrap();
+    assert_eq!(
+        serde_json::to_string(&result).unwrap(),
+        r#"{"launch":{"configurations":[{"type":"lldb","request":"launch"}]},"b":"@{missing}"}"#
+    );
+
+    // Warnings are still located in the original source
+    let mut diagnostics = Diagnostics::default();
+    generate_from_template(
+        template,
+        None,
+        &Config::default(),
+        std::env::consts::OS,
+        &mut diagnostics,
+    )
+    .unwrap();
+    let rendered = diagnostics
+        .iter()
+        .next()
+        .unwrap()
+        .render("t.json", Some(template));
+    assert!(rendered.contains("--> t.json:11:9"), "{}", rendered);
+}
+
+#[test]
+fn test_preserve_comments() {
+    let template = r#"{
+        "gen.globals": {
+            // Not in the output
+            "common": {
+                // Where to run
+                "cwd": "${workspaceFolder}",
+                "env": { "A": "@{a}" } // A is needed by the tests
+            },
+            "a": "1"
+        },
+        "launch": {
+            "configurations": [
+                {
+                    "name": "tests",
+                    // Expands to cwd and env
+                    "@@{common}": ""
+                }
+            ]
+        }
+    }"#;
+
+    let config: Config = serde_json::from_str(r#"{ "preserve_comments": true }"#).unwrap();
+    let generated = generate_from_template(
+        template,
+        None,
+        &config,
+        std::env::consts::OS,
+        &mut Diagnostics::default(),
+    )
+    .unwrap();
+
+    let expected = r#"{
+  "launch": {
+    "configurations": [
+      {
+        "name": "tests",
+        // Where to run
+        "cwd": "${workspaceFolder}",
+        // A is needed by the tests
+        "env": {
+          "A": "1"
+        }
+      }
+    ]
+  }
+}"#;
+    assert_eq!(crate::jsonc::to_string(&generated.workspace, 2), expected);
+
+    // Without the option, there's nothing to preserve
+    let generated = generate_from_template(
+        template,
+        None,
+        &Config::default(),
+        std::env::consts::OS,
+        &mut Diagnostics::default(),
+    )
+    .unwrap();
+    assert_eq!(
+        crate::jsonc::to_string(&generated.workspace, 2),
+        to_json_string(&generated.workspace, 2).unwrap()
+    );
+}
+
+#[test]
+fn test_gen_if() {
+    std::env::set_var("VSCODE_WORKSPACE_GEN_TEST_IF", "1");
+    let template = r#"{
+        "gen.globals": {
+            "compiler": "clang",
+            "asan": {
+                "gen.if": "compiler in ['gcc', 'clang']",
+                "ASAN_OPTIONS": "detect_leaks=1"
+            }
+        },
+        "configurations": [
+            { "gen.if": "compiler == 'msvc'", "name": "msvc" },
+            { "gen.if": "compiler != 'msvc' && os == 'linux'", "name": "linux" },
+            { "gen.if": "env.VSCODE_WORKSPACE_GEN_TEST_IF && !env.VSCODE_WORKSPACE_GEN_UNSET", "name": "env" },
+            { "gen.if": false, "name": "disabled" }
+        ],
+        "env": { "@@{asan}": "", "A": 1 }
+    }"#;
+
+    let result = generate_from_string(template, "linux").unwrap();
+    assert_eq!(
+        serde_json::to_string(&result).unwrap(),
+        r#"{"configurations":[{"name":"linux"},{"name":"env"}],"env":{"ASAN_OPTIONS":"detect_leaks=1","A":1}}"#
+    );
+
+    let result = generate_from_string(template, "windows").unwrap();
+    assert_eq!(result["configurations"][0]["name"], "env");
+
+    let template = r#"{
+        "launch": [
+            { "gen.if": "os == ", "name": "broken" }
+        ]
+    }"#;
+    match generate_from_string(template, "linux") {
+        Err(Error::Condition {
+            expression,
+            message,
+            json_pointer,
+        }) => {
+            assert_eq!(expression, "os == ");
+            assert_eq!(message, "Unexpected end of expression");
+            assert_eq!(json_pointer, "/launch/0/gen.if");
+        }
+        other => panic!("Expected a condition error, got {:?}", other),
+    }
+
+    assert!(matches!(
+        generate_from_string(r#"{ "a": { "gen.if": 1 } }"#, "linux"),
+        Err(Error::Condition { .. })
+    ));
+}
+
+#[test]
+fn test_variables() {
+    let template = r#"{
+        "gen.globals": {
+            "build_type": "Debug",
+            "compiler": "gcc",
+            "jobs": 1
+        },
+        "a": "build-@{build_type}-@{compiler}",
+        "b": "@{jobs}",
+        "c": "$${compiler} $${VSCODE_WORKSPACE_GEN_UNSET}",
+        "d": { "gen.if": "compiler == 'clang'" }
+    }"#;
+
+    let mut config: Config =
+        serde_json::from_str(r#"{ "variables": { "compiler": "clang", "jobs": 8 } }"#).unwrap();
+
+    // As if passed with -D
+    config.set_variable(
+        "build_type".to_string(),
+        Value::String("Release".to_string()),
+    );
+    config.set_variable("compiler".to_string(), Value::String("msvc".to_string()));
+
+    let generated = generate_from_template(
+        template,
+        None,
+        &config,
+        "linux",
+        &mut Diagnostics::default(),
+    )
+    .unwrap();
+    assert_eq!(
+        serde_json::to_string(&generated.workspace).unwrap(),
+        r#"{"a":"build-Release-msvc","b":8,"c":"msvc $${VSCODE_WORKSPACE_GEN_UNSET}"}"#
+    );
+}
+
+#[test]
+fn test_profiles() {
+    let config: Config = serde_json::from_str(
+        r#"{
+        "variables": { "build_type": "Debug", "jobs": 4 },
+        "output_filename": "dev.code-workspace",
+        "profiles": {
+            "developer": {},
+            "ci": {
+                "variables": { "build_type": "Release" },
+                "per_os_output_filenames": {
+                    "windows": "ci-windows.code-workspace",
+                    "linux": "ci-linux.code-workspace",
+                    "macos": "ci-macos.code-workspace"
+                },
+                "os_targets": ["linux", "windows"]
+            }
+        }
+    }"#,
+    )
+    .unwrap();
+
+    assert_eq!(config.profile_names(), vec!["ci", "developer"]);
+    assert!(config.with_profile("embedded").is_err());
+
+    let developer = config.with_profile("developer").unwrap();
+    assert_eq!(developer.profile(), Some("developer"));
+    assert_eq!(
+        developer.outputs().unwrap(),
+        vec![(std::env::consts::OS, &"dev.code-workspace".to_string())]
+    );
+
+    let ci = config.with_profile("ci").unwrap();
+    assert!(ci.is_valid().is_ok());
+    assert_eq!(
+        ci.outputs().unwrap(),
+        vec![
+            ("windows", &"ci-windows.code-workspace".to_string()),
+            ("linux", &"ci-linux.code-workspace".to_string())
+        ]
+    );
+
+    let template = r#"{
+        "a": "@{build_type} @{jobs}",
+        "b": { "gen.if": "profile == 'ci'" }
+    }"#;
+    let generate = |config: &Config| {
+        generate_from_template(template, None, config, "linux", &mut Diagnostics::default())
+            .unwrap()
+            .workspace
+    };
+    assert_eq!(
+        serde_json::to_string(&generate(&developer)).unwrap(),
+        r#"{"a":"Debug 4"}"#
+    );
+    assert_eq!(
+        serde_json::to_string(&generate(&ci)).unwrap(),
+        r#"{"a":"Release 4","b":{}}"#
+    );
+}
+
+#[test]
+fn test_per_os_output_filenames_null() {
+    let config: Config = serde_json::from_str(
+        r#"{ "per_os_output_filenames": { "linux": "l.json", "windows": null } }"#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        config.outputs().unwrap(),
+        vec![("linux", &"l.json".to_string())]
+    );
+    assert_eq!(config.target_oses(), Some(vec!["linux"]));
+    assert_eq!(config.configured_oses(), vec!["linux"]);
+
+    // In the config's order
+    let config: Config = serde_json::from_str(
+        r#"{ "per_os_output_filenames": { "windows": "w.json", "macos": "m.json", "linux": "l.json" } }"#,
+    )
+    .unwrap();
+    assert_eq!(
+        config.target_oses(),
+        Some(vec!["windows", "macos", "linux"])
+    );
+
+    let config: Config =
+        serde_json::from_str(r#"{ "per_os_output_filenames": { "linux": 1 } }"#).unwrap();
+    assert!(config.is_valid().is_err());
+}
+
+#[test]
+fn test_unreachable_os() {
+    let template: Value = serde_json::from_str(
+        r#"{
+        "gen.globals": {
+            "bsd": { "gen.os": "freebsd" }
+        },
+        "a": [
+            { "gen.os": ["linux", "wsl"] },
+            { "gen.os": "windows" }
+        ]
+    }"#,
+    )
+    .unwrap();
+
+    let config: Config = serde_json::from_str(
+        r#"{
+        "per_os_output_filenames": {
+            "linux": "linux.code-workspace",
+            "freebsd": "freebsd.code-workspace",
+            "windows": "windows.code-workspace"
+        },
+        "os_targets": ["linux", "freebsd"]
+    }"#,
+    )
+    .unwrap();
+    assert_eq!(
+        config.outputs().unwrap(),
+        vec![
+            ("linux", &"linux.code-workspace".to_string()),
+            ("freebsd", &"freebsd.code-workspace".to_string())
+        ]
+    );
+
+    let mut diagnostics = Diagnostics::default();
+    check_os_values(&template, &config, &mut diagnostics);
+    let warnings: Vec<(&str, Option<&str>)> = diagnostics
+        .iter()
+        .map(|d| (d.message.as_str(), d.json_pointer.as_deref()))
+        .collect();
+    assert_eq!(
+        warnings,
+        vec![
+            ("Unknown OS wsl", Some("/a/0/gen.os/1")),
+            (
+                "gen.os windows isn't generated by any target, which are: linux, freebsd",
+                Some("/a/1/gen.os")
+            )
+        ]
+    );
+
+    // Without per OS outputs, only the current OS is generated, which isn't worth warning about.
+    // Unknown OSes are still reported.
+    let mut diagnostics = Diagnostics::default();
+    check_os_values(&template, &Config::default(), &mut diagnostics);
+    let warnings: Vec<&str> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+    assert_eq!(warnings, vec!["Unknown OS wsl"]);
+}
+
+#[test]
+fn test_os_groups() {
+    let config: Config =
+        serde_json::from_str(r#"{ "os_groups": { "desktop": ["linux", "windows"] } }"#).unwrap();
+    let os_groups = config.os_groups();
+    let allowed = |os_filter: Value, os: &str| {
+        is_allowed_in_os(&serde_json::json!({ "gen.os": os_filter }), os, &os_groups)
+    };
+
+    assert!(allowed(serde_json::json!("!windows"), "linux"));
+    assert!(!allowed(serde_json::json!("!windows"), "windows"));
+    assert!(allowed(serde_json::json!("unix"), "freebsd"));
+    assert!(!allowed(serde_json::json!("unix"), "windows"));
+    assert!(allowed(serde_json::json!(["unix", "!macos"]), "linux"));
+    assert!(!allowed(serde_json::json!(["unix", "!macos"]), "macos"));
+    assert!(allowed(serde_json::json!("desktop"), "windows"));
+    assert!(!allowed(serde_json::json!("!desktop"), "linux"));
+    assert!(!allowed(serde_json::json!([]), "linux"));
+
+    let template = r#"{
+        "a": { "gen.os": "!windows", "b": 1 },
+        "c": { "gen.os": "unix", "d": 2 }
+    }"#;
+    let result = generate_from_template(
+        template,
+        None,
+        &config,
+        "windows",
+        &mut Diagnostics::default(),
+    )
+    .unwrap()
+    .workspace;
+    assert_eq!(serde_json::to_string(&result).unwrap(), "{}");
+
+    let template: Value = serde_json::from_str(
+        r#"{
+        "a": { "gen.os": "mac" },
+        "b": { "gen.os": ["!win32", "linuz", "desktop", "freebsd"] },
+        "c": { "gen.os": "amiga" }
+    }"#,
+    )
+    .unwrap();
+    let mut diagnostics = Diagnostics::default();
+    check_os_values(&template, &config, &mut diagnostics);
+    let warnings: Vec<&str> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+    assert_eq!(
+        warnings,
+        vec![
+            "Unknown OS mac, did you mean macos?",
+            "Unknown OS win32, did you mean windows?",
+            "Unknown OS linuz, did you mean linux?",
+            "Unknown OS amiga"
+        ]
+    );
+}
+
+#[test]
+fn test_select_os() {
+    let config = Config::default();
+    let template = r#"{
+        "gen.globals": {
+            "exe": { "gen.select_os": { "windows": ".exe", "default": "" } }
+        },
+        "configurations": [
+            {
+                "type": { "gen.select_os": { "windows": "cppvsdbg", "macos": "lldb", "default": "cppdbg" } },
+                "program": "app@{exe}",
+                "args": [
+                    "--verbose",
+                    { "gen.select_os": { "unix": "--no-dpi-scaling" } },
+                    { "gen.select_os": { "!windows": { "gen.select_os": { "linux": "--platform=xcb" } } } }
+                ],
+                "env": { "gen.select_os": { "linux": { "gen.os": "windows" } } }
+            }
+        ]
+    }"#;
+
+    let generate = |os: &str| {
+        let result =
+            generate_from_template(template, None, &config, os, &mut Diagnostics::default())
+                .unwrap()
+                .workspace;
+        serde_json::to_string(&result).unwrap()
+    };
+
+    assert_eq!(
+        generate("windows"),
+        r#"{"configurations":[{"type":"cppvsdbg","program":"app.exe","args":["--verbose"]}]}"#
+    );
+    assert_eq!(
+        generate("macos"),
+        r#"{"configurations":[{"type":"lldb","program":"app","args":["--verbose","--no-dpi-scaling"]}]}"#
+    );
+    assert_eq!(
+        generate("linux"),
+        r#"{"configurations":[{"type":"cppdbg","program":"app","args":["--verbose","--no-dpi-scaling","--platform=xcb"]}]}"#
+    );
+
+    let result = generate_from_template(
+        r#"{ "a": { "gen.select_os": { "linux": 1 }, "b": 2 } }"#,
+        None,
+        &config,
+        "linux",
+        &mut Diagnostics::default(),
+    );
+    assert!(matches!(
+        result,
+        Err(Error::InvalidDirective { json_pointer, .. }) if json_pointer == "/a/gen.select_os"
+    ));
+
+    let template: Value =
+        serde_json::from_str(r#"{ "a": { "gen.select_os": { "windos": 1, "default": 2 } } }"#)
+            .unwrap();
+    let mut diagnostics = Diagnostics::default();
+    check_os_values(&template, &config, &mut diagnostics);
+    let warnings: Vec<&str> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+    assert_eq!(warnings, vec!["Unknown OS windos, did you mean windows?"]);
+}
+
+#[test]
+fn test_cmake_presets() {
+    let dir = std::env::temp_dir().join(format!(
+        "vscode-workspace-gen-presets-{}",
+        std::process::id()
+    ));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let contents = r#"{
+        "gen.globals": { "build_dir": "build-dev" },
+        "settings": { "cmake.buildDirectory": "@{build_dir}" },
+        "gen.cmakepresets": {
+            "version": 3,
+            "configurePresets": [{ "name": "dev", "binaryDir": "@{build_dir}" }]
+        },
+        "gen.cmakeuserpresets": {
+            "version": 3,
+            "buildPresets": [{ "name": "mine", "configurePreset": "dev" }]
+        }
+    }"#;
+    let template = dir.join("foo.code-workspace.template");
+    std::fs::write(&template, contents).unwrap();
+
+    // The user presets know the configure presets of CMakePresets.json
+    let mut diagnostics = Diagnostics::default();
+    generate_from_template(
+        contents,
+        None,
+        &Config::default(),
+        std::env::consts::OS,
+        &mut diagnostics,
+    )
+    .unwrap();
+    assert_eq!(diagnostics.iter().count(), 0);
+
+    let presets_filename = dir.join("cmake/Presets.json");
+    let config: Config = serde_json::from_value(serde_json::json!({
+        "json_indent": 4,
+        "cmake_presets_filename": presets_filename.to_str().unwrap()
+    }))
+    .unwrap();
+
+    let outputs = generate_outputs(
+        template.to_str().unwrap(),
+        "foo.code-workspace",
+        &config,
+        std::env::consts::OS,
+    )
+    .unwrap();
+
+    let filenames: Vec<&std::path::Path> = outputs.iter().map(|o| o.filename.as_path()).collect();
+    assert_eq!(
+        filenames,
+        vec![
+            std::path::Path::new("foo.code-workspace"),
+            presets_filename.as_path(),
+            std::path::Path::new("CMakeUserPresets.json")
+        ]
+    );
+    assert_eq!(
+        outputs[1].contents,
+        "{\n    \"version\": 3,\n    \"configurePresets\": [\n        {\n            \"name\": \"dev\",\n            \"binaryDir\": \"build-dev\"\n        }\n    ]\n}"
+    );
+
+    std::fs::remove_dir_all(dir).unwrap();
+
+    let result = generate_from_template(
+        r#"{ "gen.cmakepresets": { "version": 1, "testPresets": [] } }"#,
+        None,
+        &Config::default(),
+        std::env::consts::OS,
+        &mut Diagnostics::default(),
+    );
+    assert!(matches!(
+        result,
+        Err(Error::InvalidDirective { json_pointer, .. }) if json_pointer == "/gen.cmakepresets/testPresets"
+    ));
+
+    // The presets are written once for all OSes
+    let result = generate_from_template(
+        r#"{ "gen.cmakeuserpresets": { "version": 3, "configurePresets": [{ "name": "dev", "gen.os": "linux" }] } }"#,
+        None,
+        &Config::default(),
+        std::env::consts::OS,
+        &mut Diagnostics::default(),
+    );
+    assert!(matches!(
+        result,
+        Err(Error::InvalidDirective { json_pointer, .. }) if json_pointer == "/gen.cmakeuserpresets/configurePresets/0/gen.os"
+    ));
+}
+
+#[test]
+fn test_cmake_tasks() {
+    let template = r#"{
+        "gen.cmake_tasks": true,
+        "tasks": {
+            "version": "2.0.0",
+            "tasks": [{ "label": "Test dev", "command": "ctest", "args": ["-j8"] }]
+        },
+        "gen.cmakepresets": {
+            "version": 3,
+            "configurePresets": [
+                { "name": "base", "hidden": true, "cacheVariables": { "CMAKE_CXX_COMPILER": "clang-cl" } },
+                { "name": "dev", "inherits": "base" },
+                { "name": "gcc", "cacheVariables": { "CMAKE_CXX_COMPILER": { "type": "FILEPATH", "value": "/usr/bin/g++" } } }
+            ],
+            "buildPresets": [
+                { "name": "dev", "configurePreset": "dev" },
+                { "name": "gcc", "configurePreset": "gcc" }
+            ],
+            "testPresets": [{ "name": "dev", "configurePreset": "dev" }]
+        }
+    }"#;
+
+    let expected: Value = serde_json::from_str(
+        r#"{
+        "tasks": {
+            "version": "2.0.0",
+            "tasks": [
+                { "label": "Test dev", "command": "ctest", "args": ["-j8"] },
+                { "label": "Configure dev", "type": "shell", "command": "cmake", "args": ["--preset", "dev"], "problemMatcher": [] },
+                { "label": "Configure gcc", "type": "shell", "command": "cmake", "args": ["--preset", "gcc"], "problemMatcher": [] },
+                {
+                    "label": "Build dev", "type": "shell", "command": "cmake", "args": ["--build", "--preset", "dev"],
+                    "group": "build", "problemMatcher": ["$msCompile"]
+                },
+                {
+                    "label": "Build gcc", "type": "shell", "command": "cmake", "args": ["--build", "--preset", "gcc"],
+                    "group": "build", "problemMatcher": ["$gcc"]
+                }
+            ]
+        }
+    }"#,
+    )
+    .unwrap();
+
+    let result = generate_from_template(
+        template,
+        None,
+        &Config::default(),
+        "linux",
+        &mut Diagnostics::default(),
+    )
+    .unwrap();
+    assert_eq!(result.workspace, expected);
+    assert!(result.cmake_presets.is_some());
+
+    let result = generate_from_string(
+        &String::from(r#"{ "gen.cmake_tasks": true }"#),
+        std::env::consts::OS,
+    );
+    assert!(matches!(
+        result,
+        Err(Error::InvalidDirective { json_pointer, .. }) if json_pointer == "/gen.cmake_tasks"
+    ));
+}
@@ -0,0 +1,111 @@
+// SPDX-License-Identifier: MIT
+
+// Support for --watch, which regenerates the workspace whenever its inputs change.
+// Files are polled, which works everywhere, including network and container filesystems.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+pub const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// What we know about a file, to tell whether it changed. None if it doesn't exist.
+type Signature = Option<(SystemTime, u64)>;
+
+fn signature(path: &Path) -> Signature {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+pub struct Watcher {
+    files: Vec<(PathBuf, Signature)>,
+}
+
+impl Watcher {
+    pub fn new(files: Vec<PathBuf>) -> Self {
+        let mut watcher = Self { files: Vec::new() };
+        watcher.set_files(files);
+        watcher
+    }
+
+    /// Changes the set of watched files, for example when the template includes new files
+    pub fn set_files(&mut self, files: Vec<PathBuf>) {
+        self.files = files
+            .into_iter()
+            .map(|f| {
+                let signature = signature(&f);
+                (f, signature)
+            })
+            .collect();
+    }
+
+    /// Returns whether any file was modified, created or removed since the last poll
+    pub fn poll(&mut self) -> bool {
+        let mut changed = false;
+        for (path, old_signature) in &mut self.files {
+            let new_signature = signature(path);
+            if new_signature != *old_signature {
+                *old_signature = new_signature;
+                changed = true;
+            }
+        }
+
+        changed
+    }
+
+    /// Blocks forever, calling on_change every time a file changes.
+    /// on_change returns the files to watch next.
+    pub fn run(&mut self, mut on_change: impl FnMut() -> Vec<PathBuf>) -> ! {
+        loop {
+            std::thread::sleep(POLL_INTERVAL);
+            if self.poll() {
+                let files = on_change();
+                self.set_files(files);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_poll() {
+        let dir =
+            std::env::temp_dir().join(format!("vscode-workspace-gen-watch-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let template = dir.join("foo.code-workspace.template");
+        let config = dir.join(".vscode-workspace-gen.json");
+        std::fs::write(&template, "{}").unwrap();
+
+        let mut watcher = Watcher::new(vec![template.clone(), config.clone()]);
+        assert!(!watcher.poll());
+
+        // Modified
+        std::fs::write(&template, r#"{ "a": 1 }"#).unwrap();
+        assert!(watcher.poll());
+        assert!(!watcher.poll());
+
+        // Same size, but newer
+        let file = std::fs::File::options()
+            .write(true)
+            .open(&template)
+            .unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(10))
+            .unwrap();
+        assert!(watcher.poll());
+        assert!(!watcher.poll());
+
+        // Created
+        std::fs::write(&config, "{}").unwrap();
+        assert!(watcher.poll());
+
+        // Removed
+        std::fs::remove_file(&config).unwrap();
+        assert!(watcher.poll());
+        assert!(!watcher.poll());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,93 +1,724 @@
 // SPDX-License-Identifier: MIT
 
-use std::{fs::File, io::Write};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+};
 
 use serde::Serialize;
 use serde_json::{ser::PrettyFormatter, Serializer};
 
 use crate::config::Config;
+use crate::diagnostics::{Diagnostic, Diagnostics};
 
-use crate::qt;
+use crate::{cargo, cmake, condition, include, jsonc, patch, qt};
 
-const GEN_GLOBALS_KEY: &str = "gen.globals";
+pub const GEN_GLOBALS_KEY: &str = "gen.globals";
 const GEN_DESCRIPTION_KEY: &str = "gen.description";
 const GEN_OS_KEY: &str = "gen.os";
+
+/// The OS names "gen.os" knows about, besides the ones in the config file.
+/// They're the ones Rust uses, so they match the current OS.
+const KNOWN_OSES: &[&str] = &[
+    "linux",
+    "macos",
+    "windows",
+    "freebsd",
+    "netbsd",
+    "openbsd",
+    "dragonfly",
+    "solaris",
+    "illumos",
+    "android",
+    "ios",
+];
+const GEN_IF_KEY: &str = "gen.if";
+const GEN_SELECT_OS_KEY: &str = "gen.select_os";
+/// The "gen.select_os" entry used when none matches the current OS
+const SELECT_OS_DEFAULT: &str = "default";
+const GEN_PARAMS_KEY: &str = "gen.params";
+const GEN_EXTENDS_KEY: &str = "gen.extends";
+const GEN_REMOVE_KEY: &str = "gen.remove";
+const GEN_PATCH_KEY: &str = "gen.patch";
+const GEN_FOREACH_KEY: &str = "gen.foreach";
+const GEN_AS_KEY: &str = "gen.as";
+const GEN_INDEX_AS_KEY: &str = "gen.index_as";
+const GEN_TEMPLATE_KEY: &str = "gen.template";
 const GEN_CMAKE_PRESETS_KEY: &str = "gen.cmakepresets";
+const GEN_CMAKE_USER_PRESETS_KEY: &str = "gen.cmakeuserpresets";
+const GEN_CARGO_METADATA_KEY: &str = "gen.cargo_metadata";
+const GEN_CMAKE_BUILD_DIR_KEY: &str = "gen.cmake_build_dir";
+const GEN_CMAKE_TASKS_KEY: &str = "gen.cmake_tasks";
 
 #[derive(Debug)]
 pub enum Error {
     Io(std::io::Error),
     Json(serde_json::Error),
     ExpectedRootObject,
+    /// A global references itself, directly or through other globals. Holds the chain of keys.
+    CyclicGlobals(Vec<String>),
+    /// A template includes itself, directly or through other files. Holds the chain of files.
+    IncludeCycle(Vec<String>),
+    /// An error which happened while processing an included file
+    Include {
+        filename: String,
+        error: Box<Error>,
+    },
+    /// A global used inside a larger string isn't a string, number, boolean or null
+    CannotInterpolate {
+        key: String,
+        json_pointer: String,
+    },
+    /// A reference to a global or env var which couldn't be resolved, in strict mode.
+    /// json_pointer is where in the template it was found.
+    Unresolved {
+        key: String,
+        json_pointer: String,
+    },
+    /// A "gen.*" key, or a reference, has an unexpected value
+    InvalidDirective {
+        key: String,
+        message: String,
+        json_pointer: String,
+    },
+    /// The template extracted from a workspace doesn't generate it back, because the
+    /// workspace uses template syntax itself. json_pointer is where in the workspace.
+    ExtractMismatch {
+        json_pointer: String,
+    },
+    /// A "gen.if" expression which can't be parsed
+    Condition {
+        expression: String,
+        message: String,
+        json_pointer: String,
+    },
+}
+
+impl Error {
+    /// Where in the template the error happened, if known.
+    /// Included files which can't be read or parsed are reported with Error::Include, relative
+    /// to the included file. Otherwise it's where in the template with its includes merged,
+    /// where included content is at the same place as in its own file.
+    pub fn json_pointer(&self) -> Option<&str> {
+        match self {
+            Error::Unresolved { json_pointer, .. }
+            | Error::CannotInterpolate { json_pointer, .. }
+            | Error::InvalidDirective { json_pointer, .. }
+            | Error::ExtractMismatch { json_pointer }
+            | Error::Condition { json_pointer, .. } => Some(json_pointer),
+            Error::CyclicGlobals(_)
+            | Error::Io(_)
+            | Error::Json(_)
+            | Error::ExpectedRootObject
+            | Error::IncludeCycle(_)
+            | Error::Include { .. } => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Json(e) => write!(f, "{}", e),
+            Error::ExpectedRootObject => write!(f, "Expected root object in JSON file"),
+            Error::CyclicGlobals(chain) => {
+                write!(f, "Cyclic reference in globals: {}", chain.join(" -> "))
+            }
+            Error::IncludeCycle(chain) => write!(f, "Cyclic include: {}", chain.join(" -> ")),
+            Error::Include { filename, error } => write!(f, "In {}: {}", filename, error),
+            Error::CannotInterpolate { key, .. } => write!(
+                f,
+                "Global {} is an object or array and can't be used inside a string",
+                key
+            ),
+            Error::Unresolved { key, .. } => write!(f, "Unresolved reference: {}", key),
+            Error::InvalidDirective { key, message, .. } => {
+                write!(f, "Invalid {}: {}", key, message)
+            }
+            Error::Condition {
+                expression,
+                message,
+                ..
+            } => write!(f, "Invalid {} `{}`: {}", GEN_IF_KEY, expression, message),
+            Error::ExtractMismatch { .. } => write!(
+                f,
+                "Can't extract a template, as the workspace contains template syntax which would be expanded"
+            ),
+        }
+    }
+}
+
+/// The result of expanding a template
+pub struct Generated {
+    pub workspace: serde_json::Value,
+
+    /// The contents of "gen.cmakepresets", if present
+    pub cmake_presets: Option<serde_json::Value>,
+
+    /// The contents of "gen.cmakeuserpresets", if present
+    pub cmake_user_presets: Option<serde_json::Value>,
+}
+
+/// A file which generating wants to write
+#[derive(Debug)]
+pub struct Output {
+    pub filename: PathBuf,
+    pub contents: String,
 }
 
+/// Generates and writes every file the template produces, except the ones in written, which an
+/// earlier target wrote already, like the CMake presets. Appends the files it writes to written.
 pub fn generate_from_file(
     template_filename: String,
     target_filename: String,
     config: &Config,
     current_os: &str,
+    written: &mut Vec<PathBuf>,
 ) -> Result<(), Error> {
+    let mut outputs = generate_outputs(&template_filename, &target_filename, config, current_os)?;
+    outputs.retain(|output| !written.contains(&output.filename));
+    for output in outputs {
+        if let Some(dir) = output.filename.parent() {
+            std::fs::create_dir_all(dir).map_err(Error::Io)?;
+        }
+        let mut file = File::create(&output.filename).map_err(Error::Io)?;
+        file.write_all(output.contents.as_bytes())
+            .map_err(Error::Io)?;
+        written.push(output.filename);
+    }
+
+    eprintln!("Suggested target filename: {}", target_filename);
+
+    // print cwd:
+    let cwd = std::env::current_dir().unwrap();
+    eprintln!("Current working directory: {}", cwd.display());
+
+    Ok(())
+}
+
+/// Generates the contents of every file the template produces, without writing anything.
+/// Warnings are printed to stderr.
+pub fn generate_outputs(
+    template_filename: &str,
+    target_filename: &str,
+    config: &Config,
+    current_os: &str,
+) -> Result<Vec<Output>, Error> {
     let template_contents = std::fs::read_to_string(template_filename).map_err(Error::Io)?;
 
-    let new_json = generate_from_string(&template_contents, current_os)?;
+    let mut diagnostics = Diagnostics::default();
+    let result = generate_from_template(
+        &template_contents,
+        Some(Path::new(template_filename)),
+        config,
+        current_os,
+        &mut diagnostics,
+    );
+    diagnostics.print(template_filename, Some(&template_contents));
+    let generated = result?;
 
-    // write json to target file
-    let target_file = std::fs::File::create(target_filename.clone()).map_err(Error::Io)?;
+    let contents = if config.preserve_comments() {
+        jsonc::to_string(&generated.workspace, config.json_indent())
+    } else {
+        to_json_string(&generated.workspace, config.json_indent())?
+    };
+    let mut outputs = vec![Output {
+        filename: PathBuf::from(target_filename),
+        contents,
+    }];
 
-    let indent_str = b" ".repeat(config.json_indent() as usize);
-    let formatter = PrettyFormatter::with_indent(indent_str.as_slice());
+    let presets = [
+        (generated.cmake_presets, config.cmake_presets_filename()),
+        (
+            generated.cmake_user_presets,
+            config.cmake_user_presets_filename(),
+        ),
+    ];
+    for (presets, filename) in presets {
+        // CMake doesn't accept comments
+        if let Some(mut presets) = presets {
+            jsonc::remove_comments(&mut presets);
+            outputs.push(Output {
+                filename: PathBuf::from(filename),
+                contents: to_json_string(&presets, config.json_indent())?,
+            });
+        }
+    }
 
-    // Write to target file
-    let mut serializer = Serializer::with_formatter(target_file, formatter);
-    new_json.serialize(&mut serializer).map_err(Error::Json)?;
+    Ok(outputs)
+}
 
-    println!("Suggested target filename: {}", target_filename);
+/// Warns about misspelled "gen.os" and "gen.select_os" OSes, and about the ones which none of the config's targets produce
+pub fn warn_about_os_values(template_filename: &str, config: &Config) {
+    let Ok(template_contents) = std::fs::read_to_string(template_filename) else {
+        return;
+    };
 
-    // print cwd:
-    let cwd = std::env::current_dir().unwrap();
-    println!("Current working directory: {}", cwd.display());
+    // Errors are reported when generating
+    let path = Path::new(template_filename);
+    let base_dir = path.parent().unwrap_or(Path::new("."));
+    let Ok(json) = include::parse_template(&template_contents, base_dir, Some(path), false) else {
+        return;
+    };
 
-    Ok(())
+    let mut diagnostics = Diagnostics::default();
+    check_os_values(&json, config, &mut diagnostics);
+    diagnostics.print(template_filename, Some(&template_contents));
 }
 
+/// Appends a warning for each "gen.os" value, or "gen.select_os" entry, in json which isn't a
+/// known OS or group.
+/// If the config has per OS outputs, also for each value which none of them produce.
+pub fn check_os_values(json: &serde_json::Value, config: &Config, diagnostics: &mut Diagnostics) {
+    let os_groups = config.os_groups();
+    let target_oses = config.target_oses();
+
+    let mut known_oses: Vec<&str> = KNOWN_OSES.to_vec();
+    known_oses.push(std::env::consts::OS);
+    known_oses.extend(config.configured_oses());
+    known_oses.extend(os_groups.keys().map(String::as_str));
+    known_oses.extend(os_groups.values().flatten().map(String::as_str));
+
+    for (key, filter, pointer) in os_filters(json) {
+        let (negated, name) = match filter.strip_prefix('!') {
+            Some(name) => (true, name),
+            None => (false, filter.as_str()),
+        };
+
+        if !known_oses.contains(&name) {
+            let message = match suggest_os(name, &known_oses) {
+                Some(suggestion) => format!("Unknown OS {}, did you mean {}?", name, suggestion),
+                None => format!("Unknown OS {}", name),
+            };
+            diagnostics.push(Diagnostic::warning(message, &pointer));
+            continue;
+        }
+
+        let Some(target_oses) = &target_oses else {
+            continue;
+        };
+
+        let oses = match os_groups.get(name) {
+            Some(members) => members.iter().map(String::as_str).collect(),
+            None => vec![name],
+        };
+        if !negated && !oses.iter().any(|os| target_oses.contains(os)) {
+            diagnostics.push(Diagnostic::warning(
+                format!(
+                    "{} {} isn't generated by any target, which are: {}",
+                    key,
+                    name,
+                    target_oses.join(", ")
+                ),
+                &pointer,
+            ));
+        }
+    }
+}
+
+/// Guesses which OS a misspelled one meant
+fn suggest_os<'a>(name: &str, known_oses: &[&'a str]) -> Option<&'a str> {
+    let alias = match name.to_lowercase().as_str() {
+        "win" | "win32" | "win64" | "windows_nt" => Some("windows"),
+        "mac" | "osx" | "macosx" | "darwin" => Some("macos"),
+        "gnu/linux" | "gnu" => Some("linux"),
+        _ => None,
+    };
+    if let Some(alias) = alias {
+        return known_oses.iter().find(|os| **os == alias).copied();
+    }
+
+    known_oses
+        .iter()
+        .map(|os| (edit_distance(name, os), *os))
+        .filter(|(distance, _)| *distance <= 2)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, os)| os)
+}
+
+/// The Levenshtein distance between a and b
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+/// Returns every OS named by a "gen.os" or a "gen.select_os" in value, with the key naming it
+/// and where
+fn os_filters(value: &serde_json::Value) -> Vec<(&'static str, String, String)> {
+    let mut filters = Vec::new();
+    collect_os_filters(value, "", &mut filters);
+    filters
+}
+
+fn collect_os_filters(
+    value: &serde_json::Value,
+    pointer: &str,
+    filters: &mut Vec<(&'static str, String, String)>,
+) {
+    if let Some(obj) = value.as_object() {
+        for (k, v) in obj {
+            let child = child_pointer(pointer, k);
+            if k == GEN_SELECT_OS_KEY && v.is_object() {
+                for (os, selected) in v.as_object().unwrap() {
+                    let os_pointer = child_pointer(&child, os);
+                    if os != SELECT_OS_DEFAULT && !jsonc::is_comment_key(os) {
+                        filters.push((GEN_SELECT_OS_KEY, os.clone(), os_pointer.clone()));
+                    }
+                    collect_os_filters(selected, &os_pointer, filters);
+                }
+            } else if k != GEN_OS_KEY {
+                collect_os_filters(v, &child, filters);
+            } else if let Some(os) = v.as_str() {
+                filters.push((GEN_OS_KEY, os.to_string(), child));
+            } else if let Some(oses) = v.as_array() {
+                for (i, os) in oses.iter().enumerate() {
+                    if let Some(os) = os.as_str() {
+                        let os_pointer = child_pointer(&child, &i.to_string());
+                        filters.push((GEN_OS_KEY, os.to_string(), os_pointer));
+                    }
+                }
+            }
+        }
+    } else if let Some(array) = value.as_array() {
+        for (i, v) in array.iter().enumerate() {
+            collect_os_filters(v, &child_pointer(pointer, &i.to_string()), filters);
+        }
+    }
+}
+
+/// Serializes json pretty-printed with the given amount of spaces
+pub fn to_json_string(json: &serde_json::Value, indent: u32) -> Result<String, Error> {
+    let indent_str = b" ".repeat(indent as usize);
+    let formatter = PrettyFormatter::with_indent(indent_str.as_slice());
+
+    let mut contents = Vec::new();
+    let mut serializer = Serializer::with_formatter(&mut contents, formatter);
+    json.serialize(&mut serializer).map_err(Error::Json)?;
+
+    Ok(String::from_utf8(contents).unwrap())
+}
+
+/// Included files are searched relative to the current directory
+#[cfg(test)]
 pub fn generate_from_string(
     template_contents: &str,
     current_os: &str,
 ) -> Result<serde_json::Value, Error> {
-    let mut json: serde_json::Value =
-        serde_json::from_str(template_contents).map_err(Error::Json)?;
+    generate_from_template(
+        template_contents,
+        None,
+        &Config::default(),
+        current_os,
+        &mut Diagnostics::default(),
+    )
+    .map(|generated| generated.workspace)
+}
 
-    if !json.is_object() {
-        return Err(Error::ExpectedRootObject);
-    }
+/// template_filename is where template_contents came from, if anywhere.
+/// Included files are searched relative to it.
+/// Warnings are appended to diagnostics.
+pub fn generate_from_template(
+    template_contents: &str,
+    template_filename: Option<&Path>,
+    config: &Config,
+    current_os: &str,
+    diagnostics: &mut Diagnostics,
+) -> Result<Generated, Error> {
+    let base_dir = template_filename
+        .and_then(Path::parent)
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+
+    let mut json = include::parse_template(
+        template_contents,
+        base_dir,
+        template_filename,
+        config.preserve_comments(),
+    )?;
 
     // Remove "gen.description" keys:
     if json.as_object().unwrap().contains_key(GEN_GLOBALS_KEY) {
         discard_descriptions(&mut json[GEN_GLOBALS_KEY]);
     }
 
-    if let Some(globals) = json[GEN_GLOBALS_KEY].as_object().cloned() {
-        json.as_object_mut().unwrap().remove(GEN_GLOBALS_KEY);
-        replace_nesteds(&mut json, &globals, current_os)?;
-    }
+    add_generated_globals(&mut json, base_dir)?;
+    check_presets_os_independent(&json)?;
 
-    // Honour "gen.os":
-    remove_incompatible_os(&mut json, current_os);
+    let mut context = Context {
+        current_os,
+        strict: config.strict(),
+        variables: config.variables(),
+        os_groups: config.os_groups(),
+        diagnostics: Diagnostics::default(),
+    };
 
-    // replace $${env_var} instances
-    replace_env_vars(&mut json);
+    let result = expand(&mut json, &mut context);
+    diagnostics.append(&mut context.diagnostics);
+    result?;
 
-    generate_cmake_presets(&mut json);
+    let cmake_presets = take_cmake_presets(&mut json, GEN_CMAKE_PRESETS_KEY, &[], diagnostics)?;
+    // CMakeUserPresets.json implicitly includes CMakePresets.json
+    let configure_presets = cmake_presets
+        .as_ref()
+        .map(|presets| cmake::preset_names(presets, "configurePresets"))
+        .unwrap_or_default();
+    let cmake_user_presets = take_cmake_presets(
+        &mut json,
+        GEN_CMAKE_USER_PRESETS_KEY,
+        &configure_presets,
+        diagnostics,
+    )?;
+    let presets: Vec<&serde_json::Value> = cmake_presets
+        .iter()
+        .chain(cmake_user_presets.iter())
+        .collect();
+    add_cmake_tasks(&mut json, &presets, current_os)?;
 
     qt::suggest_needed_env_vars(template_contents);
 
-    Ok(json)
+    Ok(Generated {
+        workspace: json,
+        cmake_presets,
+        cmake_user_presets,
+    })
+}
+
+/// Honours "gen.cmake_tasks", by appending a configure, build and test task per preset to
+/// "tasks.tasks". The template's own tasks win over generated ones with the same label.
+fn add_cmake_tasks(
+    json: &mut serde_json::Value,
+    presets: &[&serde_json::Value],
+    current_os: &str,
+) -> Result<(), Error> {
+    let obj = json.as_object_mut().unwrap();
+    let Some(enabled) = obj.shift_remove(GEN_CMAKE_TASKS_KEY) else {
+        return Ok(());
+    };
+
+    let invalid = |message: String| Error::InvalidDirective {
+        key: GEN_CMAKE_TASKS_KEY.to_string(),
+        message,
+        json_pointer: child_pointer("", GEN_CMAKE_TASKS_KEY),
+    };
+
+    match enabled {
+        serde_json::Value::Bool(false) => return Ok(()),
+        serde_json::Value::Bool(true) => (),
+        _ => return Err(invalid("Expected a boolean".to_string())),
+    }
+    if presets.is_empty() {
+        return Err(invalid(format!(
+            "Requires {} or {}",
+            GEN_CMAKE_PRESETS_KEY, GEN_CMAKE_USER_PRESETS_KEY
+        )));
+    }
+
+    let tasks = obj
+        .entry("tasks")
+        .or_insert_with(|| serde_json::json!({ "version": "2.0.0" }));
+    let Some(tasks) = tasks.as_object_mut() else {
+        return Err(invalid("Expected \"tasks\" to be an object".to_string()));
+    };
+    let tasks = tasks
+        .entry("tasks")
+        .or_insert_with(|| serde_json::Value::Array(Vec::new()));
+    let Some(tasks) = tasks.as_array_mut() else {
+        return Err(invalid("Expected \"tasks.tasks\" to be a list".to_string()));
+    };
+
+    for task in cmake::tasks(presets, current_os) {
+        if !tasks.iter().any(|t| t.get("label") == task.get("label")) {
+            tasks.push(task);
+        }
+    }
+
+    Ok(())
+}
+
+/// The CMake presets are written once, whichever OS targets the config has, so they can't
+/// depend on the OS
+fn check_presets_os_independent(json: &serde_json::Value) -> Result<(), Error> {
+    for key in [GEN_CMAKE_PRESETS_KEY, GEN_CMAKE_USER_PRESETS_KEY] {
+        let Some(presets) = json.get(key) else {
+            continue;
+        };
+
+        if let Some((filter_key, _, pointer)) = os_filters(presets).into_iter().next() {
+            return Err(Error::InvalidDirective {
+                key: filter_key.to_string(),
+                message: format!(
+                    "{} is written once for all OSes, use a CMake \"condition\" instead",
+                    key
+                ),
+                json_pointer: format!("{}{}", child_pointer("", key), pointer),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes the presets under key from the expanded template, and checks them, knowing the
+/// configure presets of included files. Warnings are appended to diagnostics, and the first
+/// error is returned.
+fn take_cmake_presets(
+    json: &mut serde_json::Value,
+    key: &str,
+    included_configure_presets: &[&str],
+    diagnostics: &mut Diagnostics,
+) -> Result<Option<serde_json::Value>, Error> {
+    let Some(presets) = json.as_object_mut().unwrap().shift_remove(key) else {
+        return Ok(None);
+    };
+
+    let mut without_comments = presets.clone();
+    jsonc::remove_comments(&mut without_comments);
+
+    let key_pointer = child_pointer("", key);
+    for problem in cmake::check_presets(&without_comments, included_configure_presets) {
+        let pointer = format!("{}{}", key_pointer, problem.pointer);
+        if problem.is_error {
+            return Err(Error::InvalidDirective {
+                key: key.to_string(),
+                message: problem.message,
+                json_pointer: pointer,
+            });
+        }
+        diagnostics.push(Diagnostic::warning(problem.message, &pointer));
+    }
+
+    Ok(Some(presets))
+}
+
+/// A directive which generates a global from the project, its name, and how to generate it
+type GlobalGenerator = (
+    &'static str,
+    &'static str,
+    fn(&serde_json::Value, &Path) -> Result<serde_json::Value, String>,
+);
+
+/// Replaces "gen.cargo_metadata" and "gen.cmake_build_dir" with the global they generate,
+/// unless the template has its own
+fn add_generated_globals(json: &mut serde_json::Value, base_dir: &Path) -> Result<(), Error> {
+    let generators: [GlobalGenerator; 2] = [
+        (GEN_CARGO_METADATA_KEY, cargo::GLOBAL_NAME, cargo::global),
+        (GEN_CMAKE_BUILD_DIR_KEY, cmake::GLOBAL_NAME, cmake::global),
+    ];
+
+    let obj = json.as_object_mut().unwrap();
+    for (key, name, generate) in generators {
+        let Some(source) = obj.shift_remove(key) else {
+            continue;
+        };
+
+        let global = generate(&source, base_dir).map_err(|message| Error::InvalidDirective {
+            key: key.to_string(),
+            message,
+            json_pointer: child_pointer("", key),
+        })?;
+
+        let globals = obj
+            .entry(GEN_GLOBALS_KEY)
+            .or_insert_with(|| serde_json::Value::Object(serde_json::Map::new()));
+        if let Some(globals) = globals.as_object_mut() {
+            globals.entry(name).or_insert(global);
+        }
+    }
+
+    Ok(())
+}
+
+/// Settings and diagnostics shared by the expansion passes
+struct Context<'a> {
+    current_os: &'a str,
+    /// Whether unresolved references are errors instead of warnings
+    strict: bool,
+    /// The variables from the config file and the command line
+    variables: &'a serde_json::Map<String, serde_json::Value>,
+    /// The groups "gen.os" can use, like "unix"
+    os_groups: BTreeMap<String, Vec<String>>,
+    diagnostics: Diagnostics,
+}
+
+impl Context<'_> {
+    fn warn(&mut self, message: String, pointer: &str) {
+        self.diagnostics.push(Diagnostic::warning(message, pointer));
+    }
+
+    fn unresolved(&mut self, key: &str, pointer: &str) -> Result<(), Error> {
+        if self.strict {
+            return Err(Error::Unresolved {
+                key: key.to_string(),
+                json_pointer: pointer.to_string(),
+            });
+        }
+
+        self.warn(format!("No replacement found for key: {}", key), pointer);
+        Ok(())
+    }
+}
+
+/// Appends a key or array index to a JSON pointer, escaping it as per RFC 6901
+pub fn child_pointer(pointer: &str, token: &str) -> String {
+    format!(
+        "{}/{}",
+        pointer,
+        token.replace('~', "~0").replace('/', "~1")
+    )
+}
+
+/// Runs the expansion passes which need the context
+fn expand(json: &mut serde_json::Value, context: &mut Context) -> Result<(), Error> {
+    let mut globals = match json.as_object_mut().unwrap().shift_remove(GEN_GLOBALS_KEY) {
+        Some(serde_json::Value::Object(globals)) => globals,
+        _ => serde_json::Map::new(),
+    };
+    globals.retain(|k, _| !jsonc::is_comment_key(k));
+
+    // Variables override the template's globals
+    for (name, value) in context.variables {
+        globals.insert(name.clone(), value.clone());
+    }
+    // Before resolving, so a selected value can be used inside strings
+    let mut unselected = Vec::new();
+    for (k, v) in globals.iter_mut() {
+        let pointer = child_pointer(&child_pointer("", GEN_GLOBALS_KEY), k);
+        if !select_os_values(v, context, &pointer)? {
+            unselected.push(k.clone());
+        }
+    }
+    globals.retain(|k, _| !unselected.contains(k));
+    let globals = resolve_globals(&globals, context)?;
+
+    replace_nesteds(json, &globals, context, "")?;
+
+    // Before "gen.os" and "gen.if", as the selected values can have them
+    select_os_values(json, context, "")?;
+
+    // Honour "gen.os" and "gen.if":
+    remove_disabled(json, &globals, context, "")?;
+
+    // replace $${env_var} instances
+    replace_env_vars(json, context, "")
 }
 
 pub fn discard_descriptions(value: &mut serde_json::Value) {
     if value.is_object() {
         let obj = value.as_object_mut().unwrap();
-        obj.remove(GEN_DESCRIPTION_KEY);
+        obj.shift_remove(GEN_DESCRIPTION_KEY);
         for (_, v) in obj {
             discard_descriptions(v);
         }
@@ -103,18 +734,37 @@ pub fn discard_descriptions(value: &mut serde_json::Value) {
 pub enum TokenKind {
     Nested(String),  // @{key}
     Inplace(String), // @@{key}
+    Merge(String),   // @@@{key}
     None,
 }
 
+/// How "@@@{key}" combines an array of the global with the object's own one
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ArrayMerge {
+    /// The object's array is used
+    Replace,
+    /// The object's items come after the global's
+    Append,
+    /// The object's items come before the global's
+    Prepend,
+    /// Like Append, but items already in the global's array are skipped
+    Union,
+}
+
 pub fn token_kind_from_str(s: &str) -> TokenKind {
-    if s == "@{}" || s == "@@{}" {
+    if s == "@{}" || s == "@@{}" || s == "@@@{}" {
         // We need a key name
         return TokenKind::None;
     }
 
-    if s.starts_with("@{") && s.ends_with('}') {
+    // "@{a} @{b}" is two interpolations, not a key named "a} @{b"
+    let is_key = |key: &str| !key.contains(['{', '}']);
+
+    if s.starts_with("@@@{") && s.ends_with('}') && is_key(&s[4..s.len() - 1]) {
+        TokenKind::Merge(s[4..s.len() - 1].to_string())
+    } else if s.starts_with("@{") && s.ends_with('}') && is_key(&s[2..s.len() - 1]) {
         TokenKind::Nested(s[2..s.len() - 1].to_string())
-    } else if s.starts_with("@@{") && s.ends_with('}') {
+    } else if s.starts_with("@@{") && s.ends_with('}') && is_key(&s[3..s.len() - 1]) {
         TokenKind::Inplace(s[3..s.len() - 1].to_string())
     } else {
         TokenKind::None
@@ -129,63 +779,555 @@ pub fn token_kind(value: &serde_json::Value) -> TokenKind {
     TokenKind::None
 }
 
-/// If an object contains a "gen.os" key, it will only be included if the current OS is in the list
-pub fn is_allowed_in_os(value: &serde_json::Value, current_os: &str) -> bool {
-    if let Some(obj) = value.as_object() {
-        if let Some(os) = obj.get(GEN_OS_KEY) {
-            return (os.is_array()
-                && os
-                    .as_array()
-                    .unwrap()
-                    .contains(&serde_json::Value::String(current_os.to_string())))
-                || (os.is_string() && os.as_str().unwrap() == current_os);
+/// Returns the global's name of a reference like "key(a=1, b=2)"
+fn reference_name(reference: &str) -> &str {
+    match reference.find('(') {
+        Some(pos) => reference[..pos].trim(),
+        None => reference,
+    }
+}
+
+/// Splits a reference like "key(a=1, b=2)" into the global's name and its arguments
+fn parse_reference<'a>(
+    reference: &'a str,
+    pointer: &str,
+) -> Result<(&'a str, serde_json::Map<String, serde_json::Value>), Error> {
+    let mut args = serde_json::Map::new();
+    let name = reference_name(reference);
+    if name.len() == reference.len() {
+        return Ok((name, args));
+    }
+
+    let invalid = |message: &str| Error::InvalidDirective {
+        key: reference.to_string(),
+        message: message.to_string(),
+        json_pointer: pointer.to_string(),
+    };
+
+    let args_str = reference[name.len()..]
+        .trim()
+        .strip_prefix('(')
+        .and_then(|s| s.strip_suffix(')'))
+        .ok_or_else(|| invalid("Expected arguments between parentheses"))?;
+
+    for arg in args_str.split(',').filter(|a| !a.trim().is_empty()) {
+        let (arg_name, arg_value) = arg
+            .split_once('=')
+            .ok_or_else(|| invalid("Expected arguments in the form name=value"))?;
+        args.insert(
+            arg_name.trim().to_string(),
+            serde_json::Value::String(arg_value.trim().to_string()),
+        );
+    }
+
+    Ok((name, args))
+}
+
+/// Returns a copy of the global referenced by reference.
+/// If the global declares "gen.params", the arguments passed in the reference are
+/// substituted into its "${param}" placeholders.
+fn lookup_global(
+    reference: &str,
+    globals: &serde_json::Map<String, serde_json::Value>,
+    pointer: &str,
+) -> Result<Option<serde_json::Value>, Error> {
+    let (name, args) = parse_reference(reference, pointer)?;
+    let Some(global) = find_global(name, globals) else {
+        return Ok(None);
+    };
+
+    let invalid = |message: String| Error::InvalidDirective {
+        key: reference.to_string(),
+        message,
+        json_pointer: pointer.to_string(),
+    };
+
+    let Some(params) = global.get(GEN_PARAMS_KEY) else {
+        if !args.is_empty() {
+            return Err(invalid(format!(
+                "Global {} doesn't accept parameters",
+                name
+            )));
         }
+        return Ok(Some(global.clone()));
+    };
+
+    // "gen.params" is either a list of names, or an object with default values
+    let mut values = serde_json::Map::new();
+    match params {
+        serde_json::Value::Array(names) => {
+            for param in names {
+                let param = param
+                    .as_str()
+                    .ok_or_else(|| invalid("gen.params should only contain names".to_string()))?;
+                values.insert(param.to_string(), serde_json::Value::Null);
+            }
+        }
+        serde_json::Value::Object(defaults) => values = defaults.clone(),
+        _ => {
+            return Err(invalid(
+                "gen.params should be a list or an object".to_string(),
+            ))
+        }
+    }
+
+    for (arg_name, arg_value) in args {
+        if !values.contains_key(&arg_name) {
+            return Err(invalid(format!("Unknown parameter {}", arg_name)));
+        }
+        values.insert(arg_name, arg_value);
+    }
+
+    if let Some((missing, _)) = values.iter().find(|(_, v)| v.is_null()) {
+        return Err(invalid(format!("Missing argument for {}", missing)));
     }
 
-    true
+    let mut global = global.clone();
+    global.as_object_mut().unwrap().shift_remove(GEN_PARAMS_KEY);
+    substitute_params(&mut global, &values);
+
+    Ok(Some(global))
 }
 
-/// Replaces "${key}" instances
-fn replace_nesteds(
+/// Returns the global called name. "a.b.c" is field "b.c" of global "a", if there's no global
+/// called "a.b.c". Array elements are accessed by index, like "a.0".
+fn find_global<'a>(
+    name: &str,
+    globals: &'a serde_json::Map<String, serde_json::Value>,
+) -> Option<&'a serde_json::Value> {
+    if let Some(global) = globals.get(name) {
+        return Some(global);
+    }
+
+    let (root, path) = name.split_once('.')?;
+    path.split('.')
+        .try_fold(globals.get(root)?, |value, field| match value {
+            serde_json::Value::Object(obj) => obj.get(field),
+            serde_json::Value::Array(array) => array.get(field.parse::<usize>().ok()?),
+            _ => None,
+        })
+}
+
+/// The global a reference depends on, which is "a" for field "a.b", unless "a.b" is a global
+fn global_dependency<'a>(
+    name: &'a str,
+    globals: &serde_json::Map<String, serde_json::Value>,
+) -> &'a str {
+    match name.split_once('.') {
+        Some((root, _)) if !globals.contains_key(name) => root,
+        _ => name,
+    }
+}
+
+/// Replaces "${param}" placeholders. A string consisting only of the placeholder is
+/// replaced by the value itself, so non-string default values keep their type.
+fn substitute_params(
     value: &mut serde_json::Value,
+    params: &serde_json::Map<String, serde_json::Value>,
+) {
+    if value.is_string() {
+        let s = value.as_str().unwrap();
+        if let Some(param) = s.strip_prefix("${").and_then(|p| p.strip_suffix('}')) {
+            if let Some(param_value) = params.get(param) {
+                *value = param_value.clone();
+                return;
+            }
+        }
+
+        *value = serde_json::Value::String(substitute_params_in_str(s, params));
+    } else if value.is_array() {
+        for v in value.as_array_mut().unwrap() {
+            substitute_params(v, params);
+        }
+    } else if value.is_object() {
+        let mut new_object = serde_json::Map::new();
+        for (k, mut v) in std::mem::take(value.as_object_mut().unwrap()) {
+            substitute_params(&mut v, params);
+            new_object.insert(substitute_params_in_str(&k, params), v);
+        }
+        *value = serde_json::Value::Object(new_object);
+    }
+}
+
+fn substitute_params_in_str(
+    s: &str,
+    params: &serde_json::Map<String, serde_json::Value>,
+) -> String {
+    let mut result = s.to_string();
+    for (param, param_value) in params {
+        let placeholder = format!("${{{}}}", param);
+        if result.contains(&placeholder) {
+            let replacement = match param_value {
+                serde_json::Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            result = result.replace(&placeholder, &replacement);
+        }
+    }
+
+    result
+}
+
+/// Returns the keys referenced by "@{key}", "@@{key}" and "@@@{key}" tokens, and by
+/// "gen.extends", inside value
+fn referenced_keys(value: &serde_json::Value) -> Vec<String> {
+    let mut keys = Vec::new();
+    collect_referenced_keys(value, &mut keys);
+    keys
+}
+
+fn collect_referenced_keys(value: &serde_json::Value, keys: &mut Vec<String>) {
+    if value.is_string() {
+        match token_kind(value) {
+            TokenKind::Nested(key) | TokenKind::Inplace(key) => {
+                keys.push(reference_name(&key).to_string())
+            }
+            TokenKind::Merge(_) => (),
+            TokenKind::None => keys.extend(interpolated_keys(value.as_str().unwrap())),
+        }
+    } else if value.is_array() {
+        for v in value.as_array().unwrap() {
+            collect_referenced_keys(v, keys);
+        }
+    } else if value.is_object() {
+        for (k, v) in value.as_object().unwrap() {
+            match token_kind_from_str(k.as_str()) {
+                TokenKind::Inplace(key) | TokenKind::Merge(key) => {
+                    keys.push(reference_name(&key).to_string())
+                }
+                _ => keys.extend(interpolated_keys(k)),
+            }
+
+            if k == GEN_EXTENDS_KEY {
+                let names = match v {
+                    serde_json::Value::Array(names) => names.iter().collect(),
+                    name => vec![name],
+                };
+                keys.extend(
+                    names
+                        .into_iter()
+                        .filter_map(|name| name.as_str())
+                        .map(|name| reference_name(name).to_string()),
+                );
+            }
+            collect_referenced_keys(v, keys);
+        }
+    }
+}
+
+/// Matches "@{key}" anywhere inside a string. "@@{key}" is matched too, so it can be skipped.
+fn interpolation_regex() -> regex::Regex {
+    regex::Regex::new(r"(@?)@\{([^{}]+)\}").unwrap()
+}
+
+fn interpolated_keys(s: &str) -> Vec<String> {
+    interpolation_regex()
+        .captures_iter(s)
+        .filter(|caps| caps[1].is_empty())
+        .map(|caps| reference_name(&caps[2]).to_string())
+        .collect()
+}
+
+/// Replaces "@{key}" instances which are part of a larger string, like "build-@{preset}/bin".
+/// Only globals which are strings, numbers, booleans or null can be interpolated.
+fn interpolate(
+    s: &str,
+    globals: &serde_json::Map<String, serde_json::Value>,
+    context: &mut Context,
+    pointer: &str,
+) -> Result<String, Error> {
+    let mut result = String::new();
+    let mut last_end = 0;
+    for caps in interpolation_regex().captures_iter(s) {
+        let token = caps.get(0).unwrap();
+        if !caps[1].is_empty() {
+            continue;
+        }
+
+        let key = &caps[2];
+        let replacement = match lookup_global(key, globals, pointer)? {
+            Some(serde_json::Value::String(replacement)) => replacement,
+            Some(serde_json::Value::Array(_)) | Some(serde_json::Value::Object(_)) => {
+                return Err(Error::CannotInterpolate {
+                    key: key.to_string(),
+                    json_pointer: pointer.to_string(),
+                });
+            }
+            Some(replacement) => replacement.to_string(),
+            None => {
+                context.unresolved(key, pointer)?;
+                token.as_str().to_string()
+            }
+        };
+
+        result.push_str(&s[last_end..token.start()]);
+        result.push_str(&replacement);
+        last_end = token.end();
+    }
+
+    result.push_str(&s[last_end..]);
+    Ok(result)
+}
+
+/// Expands the "@{key}" references that globals make to each other.
+/// Globals are resolved in dependency order, so a global can be composed of other globals.
+fn resolve_globals(
+    globals: &serde_json::Map<String, serde_json::Value>,
+    context: &mut Context,
+) -> Result<serde_json::Map<String, serde_json::Value>, Error> {
+    let mut resolved = serde_json::Map::new();
+    let mut chain = Vec::new();
+    for key in globals.keys() {
+        resolve_global(key, globals, &mut resolved, &mut chain, context)?;
+    }
+
+    // Keep the original order, for predictability
+    let mut ordered = serde_json::Map::new();
+    for key in globals.keys() {
+        ordered.insert(key.clone(), resolved.remove(key).unwrap());
+    }
+
+    Ok(ordered)
+}
+
+/// chain holds the keys currently being resolved, so we can detect cycles
+fn resolve_global(
+    key: &str,
     globals: &serde_json::Map<String, serde_json::Value>,
+    resolved: &mut serde_json::Map<String, serde_json::Value>,
+    chain: &mut Vec<String>,
+    context: &mut Context,
+) -> Result<(), Error> {
+    if resolved.contains_key(key) {
+        return Ok(());
+    }
+
+    if let Some(pos) = chain.iter().position(|k| k == key) {
+        let mut cycle = chain[pos..].to_vec();
+        cycle.push(key.to_string());
+        return Err(Error::CyclicGlobals(cycle));
+    }
+
+    // Unknown keys are reported when expanding
+    let Some(value) = globals.get(key) else {
+        return Ok(());
+    };
+
+    chain.push(key.to_string());
+    for dependency in referenced_keys(value) {
+        let dependency = global_dependency(&dependency, globals);
+        resolve_global(dependency, globals, resolved, chain, context)?;
+    }
+    chain.pop();
+
+    let mut value = value.clone();
+    let pointer = child_pointer(&child_pointer("", GEN_GLOBALS_KEY), key);
+    replace_nesteds(&mut value, resolved, context, &pointer)?;
+    resolved.insert(key.to_string(), value);
+
+    Ok(())
+}
+
+/// If an object contains a "gen.os" key, it will only be included if the current OS matches it.
+/// "gen.os" is an OS or a group of OSes, or a list of them. A leading "!" excludes them instead.
+pub fn is_allowed_in_os(
+    value: &serde_json::Value,
     current_os: &str,
+    os_groups: &BTreeMap<String, Vec<String>>,
+) -> bool {
+    let Some(os) = value.as_object().and_then(|obj| obj.get(GEN_OS_KEY)) else {
+        return true;
+    };
+
+    let filters: Vec<&str> = match os {
+        serde_json::Value::String(os) => vec![os.as_str()],
+        serde_json::Value::Array(oses) => oses.iter().filter_map(|os| os.as_str()).collect(),
+        _ => Vec::new(),
+    };
+    if filters.is_empty() {
+        return false;
+    }
+
+    let matches = |name: &str| os_matches(name, current_os, os_groups);
+
+    let (excluded, included): (Vec<&str>, Vec<&str>) =
+        filters.into_iter().partition(|os| os.starts_with('!'));
+
+    (included.is_empty() || included.iter().any(|os| matches(os)))
+        && !excluded.iter().any(|os| matches(&os[1..]))
+}
+
+/// Whether name is current_os, or a group containing it
+fn os_matches(name: &str, current_os: &str, os_groups: &BTreeMap<String, Vec<String>>) -> bool {
+    name == current_os
+        || os_groups
+            .get(name)
+            .is_some_and(|members| members.iter().any(|os| os == current_os))
+}
+
+/// Collapses the "gen.select_os" values in value, removing the ones which select nothing.
+/// Returns false if value itself selects nothing.
+fn select_os_values(
+    value: &mut serde_json::Value,
+    context: &Context,
+    pointer: &str,
+) -> Result<bool, Error> {
+    if !select_os(value, context, pointer)? {
+        return Ok(false);
+    }
+
+    if value.is_object() {
+        let mut unselected = Vec::new();
+        for (k, v) in value.as_object_mut().unwrap() {
+            if !select_os_values(v, context, &child_pointer(pointer, k))? {
+                unselected.push(k.clone());
+            }
+        }
+        value
+            .as_object_mut()
+            .unwrap()
+            .retain(|k, _| !unselected.contains(k));
+    } else if value.is_array() {
+        let mut selected = Vec::new();
+        for (i, v) in value.as_array_mut().unwrap().iter_mut().enumerate() {
+            selected.push(select_os_values(
+                v,
+                context,
+                &child_pointer(pointer, &i.to_string()),
+            )?);
+        }
+
+        let mut selected = selected.into_iter();
+        value
+            .as_array_mut()
+            .unwrap()
+            .retain(|_| selected.next().unwrap());
+    }
+
+    Ok(true)
+}
+
+/// Replaces a {"gen.select_os": {"windows": ..., "default": ...}} value with the entry for the
+/// current OS, or with the "default" one. Entries can be OSes, groups or negated ones, and the
+/// first match wins. Returns false if nothing matches, in which case the value is removed.
+fn select_os(
+    value: &mut serde_json::Value,
+    context: &Context,
+    pointer: &str,
+) -> Result<bool, Error> {
+    // The selected value can be a "gen.select_os" itself
+    while let Some(obj) = value.as_object() {
+        let Some(choices) = obj.get(GEN_SELECT_OS_KEY) else {
+            return Ok(true);
+        };
+
+        let invalid = |message: &str| Error::InvalidDirective {
+            key: GEN_SELECT_OS_KEY.to_string(),
+            message: message.to_string(),
+            json_pointer: child_pointer(pointer, GEN_SELECT_OS_KEY),
+        };
+
+        if obj
+            .keys()
+            .any(|k| k != GEN_SELECT_OS_KEY && !jsonc::is_comment_key(k))
+        {
+            return Err(invalid(
+                "Expected it to be the only key, as it's replaced by the selected value",
+            ));
+        }
+        let Some(choices) = choices.as_object() else {
+            return Err(invalid("Expected an object with a value per OS"));
+        };
+
+        let matches = |os: &str| match os.strip_prefix('!') {
+            Some(os) => !os_matches(os, context.current_os, &context.os_groups),
+            None => os_matches(os, context.current_os, &context.os_groups),
+        };
+        let selected = choices
+            .iter()
+            .find(|(os, _)| *os != SELECT_OS_DEFAULT && !jsonc::is_comment_key(os) && matches(os))
+            .map(|(_, v)| v)
+            .or_else(|| choices.get(SELECT_OS_DEFAULT));
+
+        match selected {
+            Some(selected) => *value = selected.clone(),
+            None => return Ok(false),
+        }
+    }
+
+    Ok(true)
+}
+
+/// Replaces "@{key}" and "@@{key}" instances
+/// pointer is the JSON pointer of value, used for error reporting
+fn replace_nesteds(
+    value: &mut serde_json::Value,
+    globals: &serde_json::Map<String, serde_json::Value>,
+    context: &mut Context,
+    pointer: &str,
 ) -> Result<(), Error> {
     if value.is_string() {
         // checks that the value conforms to the format @{contents}
         match token_kind(value) {
             TokenKind::Nested(key) => {
-                if let Some(replacement_value) = globals.get(key.as_str()) {
-                    *value = replacement_value.clone();
+                if let Some(replacement_value) = lookup_global(&key, globals, pointer)? {
+                    *value = replacement_value;
                 } else {
-                    println!("No replacement found for key: {}", key);
+                    context.unresolved(&key, pointer)?;
                 }
             }
-            TokenKind::Inplace(_) => (),
-            TokenKind::None => (),
+            TokenKind::Inplace(_) | TokenKind::Merge(_) => (),
+            TokenKind::None => {
+                let interpolated = interpolate(value.as_str().unwrap(), globals, context, pointer)?;
+                *value = serde_json::Value::String(interpolated);
+            }
         }
 
         return Ok(());
     } else if value.is_array() {
-        for v in value.as_array_mut().unwrap() {
-            replace_nesteds(v, globals, current_os)?;
+        // Expand "gen.foreach" first, so the copies are expanded like any other item, including
+        // nested loops. item_pointers keeps where in the template each item comes from.
+        let mut items = Vec::new();
+        let mut item_pointers = Vec::new();
+        for (i, v) in std::mem::take(value.as_array_mut().unwrap())
+            .into_iter()
+            .enumerate()
+        {
+            let item_pointer = child_pointer(pointer, &i.to_string());
+            match expand_foreach(&v, globals, context, &item_pointer)? {
+                Some(copies) => {
+                    let template_pointer = child_pointer(&item_pointer, GEN_TEMPLATE_KEY);
+                    item_pointers.extend(std::iter::repeat_n(template_pointer, copies.len()));
+                    items.extend(copies);
+                }
+                None => {
+                    items.push(v);
+                    item_pointers.push(item_pointer);
+                }
+            }
+        }
+        *value = serde_json::Value::Array(items);
+
+        for (v, item_pointer) in value.as_array_mut().unwrap().iter_mut().zip(&item_pointers) {
+            replace_nesteds(v, globals, context, item_pointer)?;
         }
 
         // expand $${key} instances
         let mut new_array_value = serde_json::Value::Array(vec![]);
         let new_array = new_array_value.as_array_mut().unwrap();
-        for v in value.as_array().unwrap() {
+        for (v, item_pointer) in value.as_array().unwrap().iter().zip(&item_pointers) {
+            let item_pointer = item_pointer.clone();
             if let TokenKind::Inplace(key) = token_kind(v) {
-                if let Some(replacement_value) = globals.get(key.as_str()) {
+                if let Some(replacement_value) = lookup_global(&key, globals, &item_pointer)? {
                     if replacement_value.is_array() {
                         for gv in replacement_value.as_array().unwrap() {
                             new_array.push(gv.clone());
                         }
                     } else {
-                        new_array.push(replacement_value.clone());
+                        new_array.push(replacement_value);
                     }
                 } else {
-                    println!("No replacement found for key: {}", key);
+                    context.unresolved(&key, &item_pointer)?;
                     new_array.push(v.clone());
                 }
             } else {
@@ -195,107 +1337,620 @@ fn replace_nesteds(
 
         *value = new_array_value;
     } else if value.is_object() {
-        // Replace @{key}
-        for (_, v) in value.as_object_mut().unwrap() {
-            replace_nesteds(v, globals, current_os)?;
+        // Array items with "gen.foreach" were expanded already, elsewhere it can't generate items
+        if value.as_object().unwrap().contains_key(GEN_FOREACH_KEY) {
+            return Err(Error::InvalidDirective {
+                key: GEN_FOREACH_KEY.to_string(),
+                message: format!("{} can only be used as an array item", GEN_FOREACH_KEY),
+                json_pointer: child_pointer(pointer, GEN_FOREACH_KEY),
+            });
+        }
+
+        // Replace @{key}. Comments are left as they are.
+        for (k, v) in value.as_object_mut().unwrap() {
+            if !jsonc::is_comment_key(k) {
+                replace_nesteds(v, globals, context, &child_pointer(pointer, k))?;
+            }
         }
 
         let mut new_object_value = serde_json::Value::Object(serde_json::Map::new());
         let new_object = new_object_value.as_object_mut().unwrap();
+        let obj = value.as_object().unwrap();
 
-        // Replace @@{key}
-        for (k, v) in value.as_object().unwrap() {
-            if let TokenKind::Inplace(key) = token_kind_from_str(k.as_str()) {
-                if let Some(replacement_value) = globals.get(key.as_str()) {
-                    if !is_allowed_in_os(replacement_value, current_os) {
-                        continue;
-                    }
+        // Look up the "@@@{key}" globals first, as the keys they merge into can come before them.
+        // Their values are deep-merged with each other in key order into base, and strategies
+        // holds the array merge strategy of the last "@@@{key}" which has each of base's keys.
+        let mut merges = HashMap::new();
+        let mut base = serde_json::Map::new();
+        let mut strategies = HashMap::new();
+        for (k, v) in obj {
+            if let TokenKind::Merge(key) = token_kind_from_str(k.as_str()) {
+                let key_pointer = child_pointer(pointer, k);
+                let strategy = array_merge(k, v, &key_pointer)?;
+                let replacement = inplace_object(key, globals, context, &key_pointer)?;
+                for (rk, rv) in replacement.iter().flatten() {
+                    let rv = match base.get(rk) {
+                        Some(base_value) => deep_merge(base_value, rv, strategy),
+                        None => rv.clone(),
+                    };
+                    base.insert(rk.clone(), rv);
+                    strategies.insert(rk.clone(), strategy);
+                }
+                merges.insert(k.clone(), replacement);
+            }
+        }
 
-                    if replacement_value.is_object() {
-                        for (rk, rv) in replacement_value.as_object().unwrap() {
-                            // only insert if old object does not have the key
-                            if !value.as_object().unwrap().contains_key(rk) {
-                                new_object.insert(rk.clone(), rv.clone());
-                            }
+        // Replace @@{key} and @@@{key}
+        for (k, v) in obj {
+            let key_pointer = child_pointer(pointer, k);
+            let replacement = match token_kind_from_str(k.as_str()) {
+                TokenKind::Inplace(key) => inplace_object(key, globals, context, &key_pointer)?,
+                TokenKind::Merge(_) => merges.remove(k).flatten().map(|replacement| {
+                    // The merged values of all "@@@{key}" globals, where the first one has them.
+                    // The object's own keys are merged with base below.
+                    replacement
+                        .into_iter()
+                        .filter(|(rk, _)| !obj.contains_key(rk))
+                        .filter_map(|(rk, _)| base.shift_remove(&rk).map(|merged| (rk, merged)))
+                        .collect()
+                }),
+                _ => {
+                    let v = match (base.get(k), strategies.get(k)) {
+                        (Some(base_value), Some(strategy)) => deep_merge(base_value, v, *strategy),
+                        _ => v.clone(),
+                    };
+                    new_object.insert(interpolate(k, globals, context, &key_pointer)?, v);
+                    continue;
+                }
+            };
+
+            match replacement {
+                Some(replacement) => {
+                    for (rk, rv) in replacement {
+                        // only insert if old object does not have the key
+                        if !obj.contains_key(&rk) {
+                            new_object.insert(rk, rv);
                         }
-                    } else {
-                        println!("Can only expand objects into objects");
-                        new_object.insert(k.clone(), v.clone());
                     }
-                } else {
-                    println!("No replacement found for key: {}", key);
+                }
+                None => {
                     new_object.insert(k.clone(), v.clone());
                 }
-            } else {
-                new_object.insert(k.clone(), v.clone());
             }
         }
 
+        extend(new_object, globals, context, pointer)?;
         *value = new_object_value;
+        remove_paths(value, context, pointer)?;
+        apply_patch(value, pointer)?;
+    }
+
+    Ok(())
+}
+
+/// Replaces an object which has "gen.extends" with its base globals, merged deeply in order,
+/// and then the object's own keys merged over them
+fn extend(
+    obj: &mut serde_json::Map<String, serde_json::Value>,
+    globals: &serde_json::Map<String, serde_json::Value>,
+    context: &mut Context,
+    pointer: &str,
+) -> Result<(), Error> {
+    let Some(extends) = obj.shift_remove(GEN_EXTENDS_KEY) else {
+        return Ok(());
+    };
+
+    let extends_pointer = child_pointer(pointer, GEN_EXTENDS_KEY);
+    let invalid = |message: String| Error::InvalidDirective {
+        key: GEN_EXTENDS_KEY.to_string(),
+        message,
+        json_pointer: extends_pointer.clone(),
+    };
+
+    let names = match &extends {
+        serde_json::Value::String(name) => vec![name.as_str()],
+        serde_json::Value::Array(names) => names
+            .iter()
+            .map(|name| name.as_str())
+            .collect::<Option<Vec<&str>>>()
+            .ok_or_else(|| invalid("Expected a list of global names".to_string()))?,
+        _ => {
+            return Err(invalid(
+                "Expected the name of a global, or a list of them".to_string(),
+            ))
+        }
+    };
+
+    let mut merged = serde_json::Value::Object(serde_json::Map::new());
+    for name in names {
+        match lookup_global(name, globals, &extends_pointer)? {
+            Some(base) if base.is_object() => {
+                merged = deep_merge(&merged, &base, ArrayMerge::Replace)
+            }
+            Some(_) => return Err(invalid(format!("Global {} isn't an object", name))),
+            None => context.unresolved(name, &extends_pointer)?,
+        }
+    }
+
+    let own = serde_json::Value::Object(std::mem::take(obj));
+    if let serde_json::Value::Object(merged) = deep_merge(&merged, &own, ArrayMerge::Replace) {
+        *obj = merged;
+    }
+
+    Ok(())
+}
+
+/// Expands a {"gen.foreach": [...], "gen.as": "item", "gen.template": ...} array item into a copy
+/// of the template per element of the list. "${item}" and "${item.field}" are replaced in each
+/// copy, and so is the element's index if "gen.index_as" names it.
+/// Returns None if item isn't a "gen.foreach".
+fn expand_foreach(
+    item: &serde_json::Value,
+    globals: &serde_json::Map<String, serde_json::Value>,
+    context: &mut Context,
+    pointer: &str,
+) -> Result<Option<Vec<serde_json::Value>>, Error> {
+    let Some(obj) = item
+        .as_object()
+        .filter(|obj| obj.contains_key(GEN_FOREACH_KEY))
+    else {
+        return Ok(None);
+    };
+
+    let invalid = |key: &str, message: &str| Error::InvalidDirective {
+        key: key.to_string(),
+        message: message.to_string(),
+        json_pointer: child_pointer(pointer, key),
+    };
+
+    let known_keys = [
+        GEN_FOREACH_KEY,
+        GEN_AS_KEY,
+        GEN_INDEX_AS_KEY,
+        GEN_TEMPLATE_KEY,
+    ];
+    if let Some(key) = obj
+        .keys()
+        .find(|k| !known_keys.contains(&k.as_str()) && !jsonc::is_comment_key(k))
+    {
+        return Err(invalid(
+            key,
+            &format!("Unexpected key next to {}", GEN_FOREACH_KEY),
+        ));
+    }
+
+    let Some(template) = obj.get(GEN_TEMPLATE_KEY) else {
+        return Err(invalid(
+            GEN_FOREACH_KEY,
+            &format!("Missing {}", GEN_TEMPLATE_KEY),
+        ));
+    };
+
+    let name = match obj.get(GEN_AS_KEY) {
+        None => "item",
+        Some(serde_json::Value::String(name)) => name.as_str(),
+        Some(_) => return Err(invalid(GEN_AS_KEY, "Expected a name")),
+    };
+    let index_name = match obj.get(GEN_INDEX_AS_KEY) {
+        None => None,
+        Some(serde_json::Value::String(name)) => Some(name),
+        Some(_) => return Err(invalid(GEN_INDEX_AS_KEY, "Expected a name")),
+    };
+
+    let mut list = obj[GEN_FOREACH_KEY].clone();
+    replace_nesteds(
+        &mut list,
+        globals,
+        context,
+        &child_pointer(pointer, GEN_FOREACH_KEY),
+    )?;
+    let serde_json::Value::Array(list) = list else {
+        return Err(invalid(
+            GEN_FOREACH_KEY,
+            "Expected a list, or a reference to one",
+        ));
+    };
+
+    let mut copies = Vec::new();
+    for (index, element) in list.iter().enumerate() {
+        let mut params = serde_json::Map::new();
+        if let Some(index_name) = index_name {
+            params.insert(index_name.clone(), serde_json::Value::from(index));
+        }
+        add_loop_params(name, element, &mut params);
+
+        let mut copy = template.clone();
+        substitute_loop_params(&mut copy, &params);
+
+        // A template which is a loop itself generates several items
+        let template_pointer = child_pointer(pointer, GEN_TEMPLATE_KEY);
+        match expand_foreach(&copy, globals, context, &template_pointer)? {
+            Some(nested_copies) => copies.extend(nested_copies),
+            None => copies.push(copy),
+        }
+    }
+
+    Ok(Some(copies))
+}
+
+/// Like substitute_params, but a nested "gen.foreach" rebinds its names, with "gen.as",
+/// the default "item", and "gen.index_as", so they're left alone in its "gen.template".
+fn substitute_loop_params(
+    value: &mut serde_json::Value,
+    params: &serde_json::Map<String, serde_json::Value>,
+) {
+    match value {
+        serde_json::Value::Array(array) => {
+            for v in array {
+                substitute_loop_params(v, params);
+            }
+        }
+        serde_json::Value::Object(obj) => {
+            let inner_params = obj.contains_key(GEN_FOREACH_KEY).then(|| {
+                let rebound: Vec<&str> = [
+                    obj.get(GEN_AS_KEY)
+                        .map_or(Some("item"), serde_json::Value::as_str),
+                    obj.get(GEN_INDEX_AS_KEY)
+                        .and_then(serde_json::Value::as_str),
+                ]
+                .into_iter()
+                .flatten()
+                .collect();
+
+                let mut inner_params = params.clone();
+                inner_params.retain(|param, _| {
+                    !rebound
+                        .iter()
+                        .any(|name| param == name || param.starts_with(&format!("{}.", name)))
+                });
+                inner_params
+            });
+
+            let mut new_object = serde_json::Map::new();
+            for (k, mut v) in std::mem::take(obj) {
+                match &inner_params {
+                    Some(inner_params) if k == GEN_TEMPLATE_KEY => {
+                        substitute_loop_params(&mut v, inner_params)
+                    }
+                    _ => substitute_loop_params(&mut v, params),
+                }
+                new_object.insert(substitute_params_in_str(&k, params), v);
+            }
+            *obj = new_object;
+        }
+        _ => substitute_params(value, params),
+    }
+}
+
+/// Adds value to params under name, and its fields, recursively, as "name.field"
+fn add_loop_params(
+    name: &str,
+    value: &serde_json::Value,
+    params: &mut serde_json::Map<String, serde_json::Value>,
+) {
+    params.insert(name.to_string(), value.clone());
+    if let Some(obj) = value.as_object() {
+        for (k, v) in obj {
+            add_loop_params(&format!("{}.{}", name, k), v, params);
+        }
+    }
+}
+
+/// Removes the keys listed by the object's "gen.remove", after it was expanded.
+/// They're JSON pointers relative to the object, and the leading "/" is optional.
+fn remove_paths(
+    value: &mut serde_json::Value,
+    context: &mut Context,
+    pointer: &str,
+) -> Result<(), Error> {
+    let Some(paths) = value.as_object_mut().unwrap().shift_remove(GEN_REMOVE_KEY) else {
+        return Ok(());
+    };
+
+    let remove_pointer = child_pointer(pointer, GEN_REMOVE_KEY);
+    let paths = match &paths {
+        serde_json::Value::String(path) => vec![path.as_str()],
+        serde_json::Value::Array(paths) => paths
+            .iter()
+            .map(|path| path.as_str())
+            .collect::<Option<Vec<&str>>>()
+            .ok_or_else(|| Error::InvalidDirective {
+                key: GEN_REMOVE_KEY.to_string(),
+                message: "Expected a list of paths".to_string(),
+                json_pointer: remove_pointer.clone(),
+            })?,
+        _ => {
+            return Err(Error::InvalidDirective {
+                key: GEN_REMOVE_KEY.to_string(),
+                message: "Expected a path, or a list of them".to_string(),
+                json_pointer: remove_pointer,
+            })
+        }
+    };
+
+    for path in paths {
+        let path = match path.starts_with('/') {
+            true => path.to_string(),
+            false => format!("/{}", path),
+        };
+        let operation = serde_json::json!({ "op": "remove", "path": path });
+        if patch::apply_operation(value, &operation).is_err() {
+            context.warn(
+                format!("{}: nothing to remove at {}", GEN_REMOVE_KEY, path),
+                &remove_pointer,
+            );
+        }
     }
 
     Ok(())
 }
 
-/// If an object has "gen.os", we remove it if the OS is not compatible
-fn remove_incompatible_os(value: &mut serde_json::Value, current_os: &str) {
+/// Applies the object's "gen.patch", a JSON Patch (RFC 6902), after it was expanded
+fn apply_patch(value: &mut serde_json::Value, pointer: &str) -> Result<(), Error> {
+    let Some(operations) = value.as_object_mut().unwrap().shift_remove(GEN_PATCH_KEY) else {
+        return Ok(());
+    };
+
+    let patch_pointer = child_pointer(pointer, GEN_PATCH_KEY);
+    let serde_json::Value::Array(operations) = operations else {
+        return Err(Error::InvalidDirective {
+            key: GEN_PATCH_KEY.to_string(),
+            message: "Expected a list of operations".to_string(),
+            json_pointer: patch_pointer,
+        });
+    };
+
+    for (i, operation) in operations.iter().enumerate() {
+        patch::apply_operation(value, operation).map_err(|message| Error::InvalidDirective {
+            key: GEN_PATCH_KEY.to_string(),
+            message,
+            json_pointer: child_pointer(&patch_pointer, &i.to_string()),
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Looks up the object which a "@@{key}" or "@@@{key}" object key expands to. It's empty if
+/// the global is disabled. None if it can't be expanded, in which case the key is kept as is.
+fn inplace_object(
+    key: String,
+    globals: &serde_json::Map<String, serde_json::Value>,
+    context: &mut Context,
+    key_pointer: &str,
+) -> Result<Option<serde_json::Map<String, serde_json::Value>>, Error> {
+    let Some(replacement_value) = lookup_global(&key, globals, key_pointer)? else {
+        context.unresolved(&key, key_pointer)?;
+        return Ok(None);
+    };
+
+    if !is_enabled(&replacement_value, globals, context, key_pointer)? {
+        return Ok(Some(serde_json::Map::new()));
+    }
+
+    match replacement_value {
+        serde_json::Value::Object(replacement) => Ok(Some(replacement)),
+        _ => {
+            if context.strict {
+                return Err(Error::Unresolved {
+                    key,
+                    json_pointer: key_pointer.to_string(),
+                });
+            }
+            context.warn(
+                format!("Can only expand objects into objects: {}", key),
+                key_pointer,
+            );
+            Ok(None)
+        }
+    }
+}
+
+/// Parses the value of a "@@@{key}" key, which says how arrays are merged
+fn array_merge(key: &str, value: &serde_json::Value, pointer: &str) -> Result<ArrayMerge, Error> {
+    match value.as_str() {
+        Some("") | Some("replace") => Ok(ArrayMerge::Replace),
+        Some("append") => Ok(ArrayMerge::Append),
+        Some("prepend") => Ok(ArrayMerge::Prepend),
+        Some("union") => Ok(ArrayMerge::Union),
+        _ => Err(Error::InvalidDirective {
+            key: key.to_string(),
+            message: "Expected \"\", \"replace\", \"append\", \"prepend\" or \"union\"".to_string(),
+            json_pointer: pointer.to_string(),
+        }),
+    }
+}
+
+/// Merges overlay into base, recursing into the objects both have.
+/// Arrays are combined as per strategy, and for anything else overlay wins.
+fn deep_merge(
+    base: &serde_json::Value,
+    overlay: &serde_json::Value,
+    strategy: ArrayMerge,
+) -> serde_json::Value {
+    match (base, overlay) {
+        (serde_json::Value::Object(base), serde_json::Value::Object(overlay)) => {
+            let mut merged = base.clone();
+            for (k, v) in overlay {
+                let v = match base.get(k) {
+                    Some(base_value) => deep_merge(base_value, v, strategy),
+                    None => v.clone(),
+                };
+                merged.insert(k.clone(), v);
+            }
+            serde_json::Value::Object(merged)
+        }
+        (serde_json::Value::Array(base), serde_json::Value::Array(overlay)) => {
+            let merged = match strategy {
+                ArrayMerge::Replace => overlay.clone(),
+                ArrayMerge::Append => base.iter().chain(overlay).cloned().collect(),
+                ArrayMerge::Prepend => overlay.iter().chain(base).cloned().collect(),
+                ArrayMerge::Union => {
+                    let mut merged = base.clone();
+                    for v in overlay {
+                        if !merged.contains(v) {
+                            merged.push(v.clone());
+                        }
+                    }
+                    merged
+                }
+            };
+            serde_json::Value::Array(merged)
+        }
+        _ => overlay.clone(),
+    }
+}
+
+/// Resolves an identifier of a "gen.if" expression: "os", "env.NAME" or a global which isn't
+/// an object nor an array
+fn condition_variable(
+    name: &str,
+    globals: &serde_json::Map<String, serde_json::Value>,
+    context: &Context,
+) -> Option<serde_json::Value> {
+    if name == "os" {
+        return Some(serde_json::Value::String(context.current_os.to_string()));
+    }
+
+    if let Some(env_var) = name.strip_prefix("env.") {
+        return std::env::var(env_var).ok().map(serde_json::Value::String);
+    }
+
+    find_global(name, globals)
+        .filter(|v| !v.is_object() && !v.is_array())
+        .cloned()
+}
+
+/// Whether an object should be kept, as per its "gen.os" and "gen.if"
+/// pointer is the JSON pointer of value, used for error reporting
+fn is_enabled(
+    value: &serde_json::Value,
+    globals: &serde_json::Map<String, serde_json::Value>,
+    context: &Context,
+    pointer: &str,
+) -> Result<bool, Error> {
+    if !is_allowed_in_os(value, context.current_os, &context.os_groups) {
+        return Ok(false);
+    }
+
+    let Some(condition) = value.as_object().and_then(|obj| obj.get(GEN_IF_KEY)) else {
+        return Ok(true);
+    };
+
+    let invalid = |expression: String, message: String| Error::Condition {
+        expression,
+        message,
+        json_pointer: child_pointer(pointer, GEN_IF_KEY),
+    };
+
+    match condition {
+        serde_json::Value::Bool(enabled) => Ok(*enabled),
+        serde_json::Value::String(expression) => {
+            let lookup = |name: &str| condition_variable(name, globals, context);
+            condition::evaluate(expression, &lookup)
+                .map_err(|message| invalid(expression.clone(), message))
+        }
+        other => Err(invalid(
+            other.to_string(),
+            "Expected an expression or a boolean".to_string(),
+        )),
+    }
+}
+
+/// Removes the objects whose "gen.os" or "gen.if" don't match, and then those keys
+fn remove_disabled(
+    value: &mut serde_json::Value,
+    globals: &serde_json::Map<String, serde_json::Value>,
+    context: &Context,
+    pointer: &str,
+) -> Result<(), Error> {
     if value.is_object() {
         let value_obj = value.as_object_mut().unwrap();
 
-        value_obj.retain(|_, v| is_allowed_in_os(v, current_os));
+        let mut disabled = Vec::new();
+        for (k, v) in value_obj.iter() {
+            if !is_enabled(v, globals, context, &child_pointer(pointer, k))? {
+                disabled.push(k.clone());
+            }
+        }
+        value_obj.retain(|k, _| !disabled.contains(k));
 
-        value_obj.remove(GEN_OS_KEY);
+        value_obj.shift_remove(GEN_OS_KEY);
+        value_obj.shift_remove(GEN_IF_KEY);
 
-        // obj.remove(GEN_OS_KEY);
-        for (_, v) in value_obj {
-            remove_incompatible_os(v, current_os);
+        for (k, v) in value_obj {
+            remove_disabled(v, globals, context, &child_pointer(pointer, k))?;
         }
     } else if value.is_array() {
         let value_array = value.as_array_mut().unwrap();
-        value_array.retain(|v| is_allowed_in_os(v, current_os));
 
-        for v in value_array {
-            remove_incompatible_os(v, current_os);
+        // Recurse first, so pointers still match the indexes before removal
+        let mut enabled = Vec::new();
+        for (i, v) in value_array.iter_mut().enumerate() {
+            let item_pointer = child_pointer(pointer, &i.to_string());
+            let is_enabled = is_enabled(v, globals, context, &item_pointer)?;
+            if is_enabled {
+                remove_disabled(v, globals, context, &item_pointer)?;
+            }
+            enabled.push(is_enabled);
         }
+
+        let mut enabled = enabled.into_iter();
+        value_array.retain(|_| enabled.next().unwrap());
     }
+
+    Ok(())
 }
 
-fn replace_env_vars(value: &mut serde_json::Value) {
+/// Replaces $${env_var} instances. Variables from the config and the command line have priority
+/// over environment variables. Unset ones are left as they are, unless in strict mode.
+fn replace_env_vars(
+    value: &mut serde_json::Value,
+    context: &mut Context,
+    pointer: &str,
+) -> Result<(), Error> {
     if value.is_string() {
-        let mut new_value = value.as_str().unwrap().to_string();
+        let mut new_value = String::new();
+        let mut last_end = 0;
 
         // regexp for $${key} and replace key with env var, if it exists
         let re = regex::Regex::new(r"\$\$\{([A-Za-z0-9_]+)\}").unwrap();
-        new_value = re
-            .replace_all(
-                new_value.as_str(),
-                |caps: &regex::Captures| match std::env::var(caps.get(1).unwrap().as_str()) {
+        let string_value = value.as_str().unwrap();
+        for caps in re.captures_iter(string_value) {
+            let token = caps.get(0).unwrap();
+            let replacement = match context.variables.get(&caps[1]) {
+                Some(serde_json::Value::String(variable)) => variable.clone(),
+                Some(variable) => variable.to_string(),
+                None => match std::env::var(&caps[1]) {
                     Ok(val) => val,
-                    Err(_) => caps.get(0).unwrap().as_str().to_string(),
+                    Err(_) if context.strict => {
+                        return Err(Error::Unresolved {
+                            key: caps[1].to_string(),
+                            json_pointer: pointer.to_string(),
+                        });
+                    }
+                    Err(_) => token.as_str().to_string(),
                 },
-            )
-            .to_string();
+            };
+
+            new_value.push_str(&string_value[last_end..token.start()]);
+            new_value.push_str(&replacement);
+            last_end = token.end();
+        }
+        new_value.push_str(&string_value[last_end..]);
 
         *value = serde_json::Value::String(new_value);
     } else if value.is_object() {
-        for (_, v) in value.as_object_mut().unwrap() {
-            replace_env_vars(v);
+        for (k, v) in value.as_object_mut().unwrap() {
+            if !jsonc::is_comment_key(k) {
+                replace_env_vars(v, context, &child_pointer(pointer, k))?;
+            }
         }
     } else if value.is_array() {
-        for v in value.as_array_mut().unwrap() {
-            replace_env_vars(v);
+        for (i, v) in value.as_array_mut().unwrap().iter_mut().enumerate() {
+            replace_env_vars(v, context, &child_pointer(pointer, &i.to_string()))?;
         }
     }
-}
-
-fn generate_cmake_presets(json: &mut serde_json::Value) -> Option<()> {
-    if let Some(presets) = json.as_object()?.get(GEN_CMAKE_PRESETS_KEY) {
-        // write the pretty json to a file called CMakePresets.json
-        let mut file = File::create("CMakePresets.json").ok()?;
-        let presets_str = serde_json::to_string_pretty(presets).ok()?;
-        file.write_all(presets_str.as_bytes()).ok()?;
-    }
 
-    json.as_object_mut()?.remove(GEN_CMAKE_PRESETS_KEY);
-    Some(())
+    Ok(())
 }